@@ -1,6 +1,15 @@
+use std::convert::Infallible;
+use futures::{future, Future};
+use namespace::TrailingSlash;
 use prelude::*;
 
 pub type ApiResult = Result<Response>;
+/// Future of response made by an `AsyncApi`.
+pub type ApiFuture = Box<dyn Future<Item=Response, Error=Error>>;
+/// Future of asynchronous routing. The request is given back along with the
+/// result, so that the lower hierarchy can still access it afterwards. It can't
+/// fail, as its error type is uninhabited; errors are carried in the result.
+pub type RouteFuture = Box<dyn Future<Item=(Request, ApiResult), Error=Infallible>>;
 
 /// # Writium API
 ///
@@ -11,6 +20,14 @@ pub trait Api: 'static + Send + Sync {
     /// Name of API. It identifies an API and allow Writium to route by URL path
     /// segments.
     ///
+    /// # Patterns
+    ///
+    /// Segments of the name can be patterns. `:name` matches any single path
+    /// segment and `*name` matches all the remaining ones. The matched values
+    /// are captured and can be retrieved by `Request::param()`, e.g., the name
+    /// `["books", ":isbn", "chapters", ":n"]` matches path
+    /// `/books/978-1-107-63682-8/chapters/3`. See module `pattern` for details.
+    ///
     /// # Tricks
     ///
     /// If the name of an API is an empty slice, it can work as a "fuse" - the
@@ -27,4 +44,167 @@ pub trait Api: 'static + Send + Sync {
     /// Route incoming request to the next level. If it's an end-point, the
     /// request is processed and a result is returned.
     fn route(&self, req: &mut Request) -> ApiResult;
+
+    /// Route incoming request asynchronously. `Writium` always routes requests
+    /// in this way.
+    ///
+    /// By default `route()` is called and the result is returned immediately,
+    /// so synchronous APIs don't need to implement it. To implement an
+    /// asynchronous API, see `AsyncApi`.
+    fn route_async(&self, mut req: Request) -> RouteFuture {
+        let res = self.route(&mut req);
+        Box::new(future::ok((req, res)))
+    }
+
+    /// Maximum size of request body in bytes, for the request with the given
+    /// path segments (not including the name of this API). `None` means the
+    /// limit is inherited from lower hierarchy.
+    ///
+    /// The limit is determined before the request body is received, so the
+    /// request itself is not available.
+    fn body_limit(&self, _path_segs: &[String]) -> Option<usize> {
+        None
+    }
+    /// Whether to receive the body of the request with the given path segments
+    /// (not including the name of this API) as a stream rather than a buffer.
+    /// See `Request` for more information.
+    ///
+    /// Streams can only be consumed asynchronously, so it should only be
+    /// enabled for `AsyncApi`s. Requests with body streams are never routed on
+    /// worker threads.
+    fn stream_body(&self, _path_segs: &[String]) -> bool {
+        false
+    }
+    /// Whether the request with the given path segments (not including the
+    /// name of this API) should be routed by `route_async()` rather than
+    /// `route()`. Such requests are never routed on worker threads, as their
+    /// futures may rely on the event loop. APIs implementing `route_async()`
+    /// should return `true`.
+    fn is_async(&self, _path_segs: &[String]) -> bool {
+        false
+    }
+    /// Trailing slash policy for the request with the given path segments (not
+    /// including the name of this API). `None` means the policy is inherited
+    /// from lower hierarchy. See `TrailingSlash` for more information.
+    ///
+    /// By default the request to the API itself inherits the policy, while
+    /// requests to paths under it, e.g., files, are never redirected. APIs
+    /// routing sub-paths on their own can return `None` for them as well.
+    fn trailing_slash(&self, path_segs: &[String]) -> Option<TrailingSlash> {
+        if path_segs.is_empty() {
+            None
+        } else {
+            Some(TrailingSlash::Ignore)
+        }
+    }
+    /// Handle an error occurred before the request is routed, e.g., the path
+    /// is invalid or the request body is too large. APIs having sub-APIs should
+    /// pass the error to the sub-API the request would be routed to, so that
+    /// the error is handled in the same way as those occurred in routing.
+    ///
+    /// By default the error is returned as is.
+    fn route_error(&self, _req: &mut Request, err: Error) -> ApiResult {
+        Err(err)
+    }
+
+    /// Describe the API for introspection. APIs having sub-APIs should
+    /// describe them as children.
+    fn info(&self) -> RouteInfo {
+        RouteInfo::new(self.name(), RouteKind::Api)
+            .with_doc(self.doc())
+    }
+
+    /// Metadata of the API used to generate documents. See module `openapi`.
+    fn doc(&self) -> Option<ApiDoc> {
+        None
+    }
+}
+
+/// # Asynchronous Writium API
+///
+/// `AsyncApi` is the asynchronous flavor of `Api`, responding with a `Future`,
+/// so that I/O like database queries doesn't block the event loop. It has to be
+/// wrapped in `Async` before binding; or use `Namespace`'s `bind_async()`.
+///
+/// The request is borrowed only when `route()` is called, so everything needed
+/// by the returned future should be taken from the request in advance.
+pub trait AsyncApi: 'static + Send + Sync {
+    /// Name of API. See `Api`'s `name()` for more information.
+    fn name(&self) -> &[&str];
+
+    /// Route incoming request to the next level and return a future of the
+    /// result.
+    fn route(&self, req: &mut Request) -> ApiFuture;
+
+    /// Whether to receive request body as a stream. See `Api`'s
+    /// `stream_body()` for more information.
+    fn stream_body(&self, _path_segs: &[String]) -> bool {
+        false
+    }
+
+    /// Metadata of the API used to generate documents. See module `openapi`.
+    fn doc(&self) -> Option<ApiDoc> {
+        None
+    }
+}
+
+/// Adapter making an `AsyncApi` an `Api`.
+///
+/// It can only be routed asynchronously, i.e., by `Api`'s `route_async()`, as
+/// `Writium` does, even if a worker thread pool is used. Waiting for the future in `route()` would block the current
+/// thread, which deadlocks if it's the thread of the event loop the future
+/// relies on; so `route()` fails with `500 Internal Server Error` instead.
+pub struct Async<A: AsyncApi>(pub A);
+impl<A: AsyncApi> Api for Async<A> {
+    fn name(&self) -> &[&str] {
+        self.0.name()
+    }
+    fn route(&self, _req: &mut Request) -> ApiResult {
+        error!("Asynchronous API `/{}` is routed synchronously.", self.name().join("/"));
+        Err(Error::internal("Asynchronous API cannot be routed synchronously."))
+    }
+    fn route_async(&self, mut req: Request) -> RouteFuture {
+        let f_res = self.0.route(&mut req)
+            .then(move |res| Ok((req, res)));
+        Box::new(f_res)
+    }
+    fn stream_body(&self, path_segs: &[String]) -> bool {
+        self.0.stream_body(path_segs)
+    }
+    fn is_async(&self, _path_segs: &[String]) -> bool {
+        true
+    }
+    fn doc(&self) -> Option<ApiDoc> {
+        self.0.doc()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future};
+    use prelude::*;
+
+    struct Later;
+    impl AsyncApi for Later {
+        fn name(&self) -> &[&str] {
+            &["later"]
+        }
+        fn route(&self, _req: &mut Request) -> ApiFuture {
+            Box::new(future::lazy(|| Ok(Response::new().with_status(StatusCode::Accepted))))
+        }
+    }
+
+    #[test]
+    fn async_api_is_routed_asynchronously() {
+        let (_, res) = Async(Later).route_async(Request::new(Method::Get))
+            .wait()
+            .unwrap_or_else(|never| match never {});
+        assert_eq!(res.unwrap().status(), StatusCode::Accepted);
+    }
+    #[test]
+    fn async_api_is_not_routed_synchronously() {
+        let err = Async(Later).route(&mut Request::new(Method::Get)).unwrap_err();
+        assert_eq!(err.status(), StatusCode::InternalServerError);
+    }
 }
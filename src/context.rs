@@ -0,0 +1,147 @@
+//! Information of requests in flight.
+//!
+//! `ErrorContext` describes the request an error occurred in, for both
+//! renderers (see module `render`) and observers (see module `observe`) of
+//! errors.
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use hyper::header::Accept;
+use error::ErrorFormat;
+use prelude::*;
+
+/// Request information available to renderers and observers of errors.
+///
+/// Every request is identified by a request ID. The ID is taken from the
+/// `X-Request-Id` header if the client (or the proxy) has given one with at
+/// most 128 visible ASCII characters; otherwise a process-unique one is
+/// generated. The ID is echoed in the response as `X-Request-Id`, and APIs can
+/// get it by `Request`'s `request_id()`.
+#[derive(Clone, Debug)]
+pub struct ErrorContext {
+    method: Method,
+    path: String,
+    request_id: String,
+    start: Instant,
+    accept: Option<Accept>,
+    format: ErrorFormat,
+}
+impl ErrorContext {
+    /// Create a context of a request starting now, with a generated request
+    /// ID.
+    pub fn new(method: Method, path: &str) -> ErrorContext {
+        ErrorContext {
+            method,
+            path: path.to_owned(),
+            request_id: gen_request_id(),
+            start: Instant::now(),
+            accept: None,
+            format: ErrorFormat::Problem,
+        }
+    }
+    pub fn with_request_id(mut self, request_id: &str) -> ErrorContext {
+        self.request_id = request_id.to_owned();
+        self
+    }
+    pub fn with_accept(mut self, accept: Accept) -> ErrorContext {
+        self.accept = Some(accept);
+        self
+    }
+    pub fn with_format(mut self, format: ErrorFormat) -> ErrorContext {
+        self.format = format;
+        self
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+    /// Path of the request, not including the query.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+    /// Time elapsed since the request was received.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    /// `Accept` header of the request, if any.
+    pub fn accept(&self) -> Option<&Accept> {
+        self.accept.as_ref()
+    }
+    /// Format of JSON error bodies configured on `Writium`.
+    pub fn format(&self) -> ErrorFormat {
+        self.format
+    }
+}
+
+/// Header carrying request IDs, in both requests and responses.
+pub(crate) const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Take the request ID given in headers, if it's acceptable.
+pub(crate) fn given_request_id(headers: &Headers) -> Option<&str> {
+    headers.get_raw(REQUEST_ID_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|x| ::std::str::from_utf8(x).ok())
+        .filter(|x| {
+            !x.is_empty() && x.len() <= 128 &&
+                x.bytes().all(|c| c.is_ascii_graphic())
+        })
+}
+/// Generate a process-unique request ID, with the time the first ID was
+/// generated as prefix to tell runs apart.
+fn gen_request_id() -> String {
+    static PREFIX: OnceLock<u64> = OnceLock::new();
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let prefix = PREFIX.get_or_init(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default()
+    });
+    format!("{:x}-{:x}", prefix, COUNT.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use prelude::*;
+    use testing::TestClient;
+    use super::given_request_id;
+
+    #[test]
+    fn given_request_ids_are_checked() {
+        let given = |id: &str| {
+            let mut headers = Headers::new();
+            headers.set_raw("X-Request-Id", id.to_owned());
+            given_request_id(&headers).map(|x| x.to_owned())
+        };
+        assert_eq!(given("abc-123").as_deref(), Some("abc-123"));
+        assert_eq!(given(""), None);
+        assert_eq!(given("a b"), None);
+        assert_eq!(given(&"x".repeat(129)), None);
+    }
+
+    struct Echo;
+    impl Api for Echo {
+        fn name(&self) -> &[&str] {
+            &["echo"]
+        }
+        fn route(&self, req: &mut Request) -> ApiResult {
+            Ok(Response::new().with_body(req.request_id().to_owned()))
+        }
+    }
+    #[test]
+    fn request_ids_are_echoed() {
+        let client = TestClient::for_api(Echo);
+        client.get("/echo").with_raw_header("X-Request-Id", "abc").send()
+            .assert_raw_header("X-Request-Id", "abc")
+            .assert_body("abc");
+        let res = client.get("/echo").with_raw_header("X-Request-Id", "a b").send();
+        let generated = res.text().to_owned();
+        assert!(!generated.is_empty() && generated != "a b");
+        res.assert_raw_header("X-Request-Id", &generated);
+        // Errors have it as well.
+        client.get("/missing").with_raw_header("X-Request-Id", "abc").send()
+            .assert_raw_header("X-Request-Id", "abc");
+    }
+}
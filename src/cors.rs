@@ -0,0 +1,392 @@
+use hyper::header::{AccessControlAllowCredentials, AccessControlAllowHeaders,
+    AccessControlAllowMethods, AccessControlAllowOrigin,
+    AccessControlExposeHeaders, AccessControlMaxAge, AccessControlRequestHeaders,
+    AccessControlRequestMethod, Origin, Vary};
+use unicase::Ascii;
+use prelude::*;
+
+/// `Cors` is a middleware implementing Cross-Origin Resource Sharing.
+///
+/// Preflight requests (`OPTIONS` requests with `Access-Control-Request-Method`)
+/// are answered directly without reaching the inner APIs. Both responses and
+/// errors of actual requests are decorated with `Access-Control-*` headers,
+/// if the origin is allowed.
+///
+/// Origins can be given as patterns in which `*` matches any sequence of
+/// characters, e.g., `https://*.example.com`. If no origin is given, all
+/// origins are allowed. If no allowed request header is given, the headers
+/// requested in preflights are all allowed. Preflights requesting methods or
+/// headers not allowed are rejected with `403 Forbidden`.
+///
+/// Credentials are only allowed for the origins given explicitly: if no origin
+/// is given, `Access-Control-Allow-Origin: *` is responded, with which
+/// browsers never send credentials. The `null` origin, which sandboxed pages
+/// and local files have, is never allowed with credentials.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u32>,
+}
+impl Cors {
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: vec![
+                Method::Get,
+                Method::Head,
+                Method::Post,
+                Method::Put,
+                Method::Patch,
+                Method::Delete,
+            ],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow an origin or origins matching the pattern.
+    pub fn with_origin(mut self, origin: &str) -> Cors {
+        self.origins.push(origin.to_owned());
+        self
+    }
+    /// Set the methods allowed in actual requests.
+    pub fn with_methods(mut self, methods: Vec<Method>) -> Cors {
+        self.methods = methods;
+        self
+    }
+    /// Allow a header in actual requests.
+    pub fn with_header(mut self, header: &str) -> Cors {
+        self.headers.push(header.to_owned());
+        self
+    }
+    /// Expose a response header to clients.
+    pub fn with_exposed_header(mut self, header: &str) -> Cors {
+        self.expose_headers.push(header.to_owned());
+        self
+    }
+    /// Allow credentials (cookies, authorization headers, etc.). It takes
+    /// effect only when origins are given. See `Cors` for more information.
+    pub fn with_credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+    /// Allow clients to cache preflight results for the given seconds.
+    pub fn with_max_age(mut self, max_age: u32) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Whether credentials are allowed for the allowed origins.
+    fn allows_credentials(&self) -> bool {
+        self.credentials && !self.origins.is_empty()
+    }
+    fn is_origin_allowed(&self, origin: &str) -> bool {
+        if self.origins.is_empty() {
+            true
+        } else if origin == "null" && self.credentials {
+            false
+        } else {
+            self.origins.iter().any(|x| match_wildcard(x, origin))
+        }
+    }
+    /// Get the origin of request if it's allowed.
+    fn allowed_origin(&self, req: &Request) -> Option<String> {
+        req.header::<Origin>()
+            .map(|x| x.to_string())
+            .filter(|x| self.is_origin_allowed(x))
+    }
+    fn decorate(&self, headers: &mut Headers, origin: String) {
+        if self.origins.is_empty() {
+            headers.set(AccessControlAllowOrigin::Any);
+        } else {
+            headers.set(AccessControlAllowOrigin::Value(origin));
+            // The response varies on origin as it's echoed.
+            let origin = Ascii::new("Origin".to_owned());
+            let vary = match headers.get::<Vary>() {
+                Some(Vary::Any) => None,
+                Some(Vary::Items(items)) if items.contains(&origin) => None,
+                Some(Vary::Items(items)) => {
+                    let mut items = items.clone();
+                    items.push(origin);
+                    Some(Vary::Items(items))
+                },
+                None => Some(Vary::Items(vec![origin])),
+            };
+            if let Some(vary) = vary {
+                headers.set(vary);
+            }
+        }
+        if self.allows_credentials() {
+            headers.set(AccessControlAllowCredentials);
+        }
+        if !self.expose_headers.is_empty() {
+            let expose_headers = self.expose_headers.iter()
+                .map(|x| Ascii::new(x.clone()))
+                .collect();
+            headers.set(AccessControlExposeHeaders(expose_headers));
+        }
+    }
+    /// Check whether the method and headers requested in preflight are
+    /// allowed.
+    fn check_preflight(&self, req: &Request) -> Result<()> {
+        let is_method_allowed = req.header::<AccessControlRequestMethod>()
+            .is_some_and(|x| self.methods.contains(&x.0));
+        if !is_method_allowed {
+            return Err(Error::forbidden("Method is not allowed."))
+        }
+        let are_headers_allowed = self.headers.is_empty() ||
+            req.header::<AccessControlRequestHeaders>()
+                .is_none_or(|x| x.0.iter().all(|requested| {
+                    self.headers.iter().any(|x| requested.eq_ignore_ascii_case(x))
+                }));
+        if !are_headers_allowed {
+            return Err(Error::forbidden("Headers are not allowed."))
+        }
+        Ok(())
+    }
+    fn preflight(&self, req: &Request, origin: String) -> Response {
+        let mut res = Response::new()
+            .with_status(StatusCode::NoContent)
+            .with_header(AccessControlAllowMethods(self.methods.clone()));
+        if self.headers.is_empty() {
+            if let Some(requested) = req.header::<AccessControlRequestHeaders>() {
+                res.set_header(AccessControlAllowHeaders(requested.0.clone()));
+            }
+        } else {
+            let headers = self.headers.iter()
+                .map(|x| Ascii::new(x.clone()))
+                .collect();
+            res.set_header(AccessControlAllowHeaders(headers));
+        }
+        if let Some(max_age) = self.max_age {
+            res.set_header(AccessControlMaxAge(max_age));
+        }
+        self.decorate(res.headers_mut(), origin);
+        res
+    }
+}
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+impl Middleware for Cors {
+    fn before(&self, req: &mut Request) -> Result<Option<Response>> {
+        let is_preflight = req.method() == Method::Options &&
+            req.header::<AccessControlRequestMethod>().is_some();
+        if !is_preflight {
+            return Ok(None)
+        }
+        let origin = self.allowed_origin(req)
+            .ok_or_else(|| Error::forbidden("Origin is not allowed."))?;
+        self.check_preflight(req)?;
+        Ok(Some(self.preflight(req, origin)))
+    }
+    fn after(&self, req: &Request, mut res: Response) -> ApiResult {
+        if let Some(origin) = self.allowed_origin(req) {
+            self.decorate(res.headers_mut(), origin);
+        }
+        Ok(res)
+    }
+    fn error(&self, req: &Request, mut err: Error) -> ApiResult {
+        if let Some(origin) = self.allowed_origin(req) {
+            self.decorate(err.headers_mut(), origin);
+        }
+        Err(err)
+    }
+}
+
+/// Match a string against a pattern in which `*` matches any sequence of
+/// characters.
+fn match_wildcard(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // There is always at least one part.
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match s.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    if let Some((last, mid)) = parts.split_last() {
+        for part in mid {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+        rest.len() >= last.len() && rest.ends_with(last)
+    } else {
+        // No wildcard at all.
+        rest.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{AccessControlAllowCredentials, AccessControlAllowHeaders,
+        AccessControlAllowMethods, AccessControlAllowOrigin,
+        AccessControlExposeHeaders, AccessControlMaxAge,
+        AccessControlRequestHeaders, AccessControlRequestMethod, Origin, Vary};
+    use unicase::Ascii;
+    use prelude::*;
+    use super::match_wildcard;
+
+    fn request(method: Method, origin: Origin) -> Request {
+        Request::new(method)
+            .with_header(origin)
+    }
+    fn preflight(origin: Origin, method: Method) -> Request {
+        request(Method::Options, origin)
+            .with_header(AccessControlRequestMethod(method))
+    }
+    fn example() -> Origin {
+        Origin::new("https", "app.example.com", None)
+    }
+    fn evil() -> Origin {
+        Origin::new("https", "evil.com", None)
+    }
+    fn allow_origin(headers: &Headers) -> Option<&AccessControlAllowOrigin> {
+        headers.get::<AccessControlAllowOrigin>()
+    }
+    fn decorate(cors: &Cors, req: &Request) -> Response {
+        cors.after(req, Response::new()).unwrap()
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(match_wildcard("https://example.com", "https://example.com"));
+        assert!(!match_wildcard("https://example.com", "https://example.com.evil"));
+        assert!(match_wildcard("https://*.example.com", "https://a.b.example.com"));
+        assert!(!match_wildcard("https://*.example.com", "https://example.com"));
+        assert!(!match_wildcard("https://*.example.com", "https://evil.com/.example.com.x"));
+        assert!(match_wildcard("*", ""));
+        assert!(match_wildcard("a*b*c", "abc"));
+        assert!(match_wildcard("a*b*c", "axxbyyc"));
+        assert!(!match_wildcard("a*b*c", "acb"));
+        // Parts must not overlap.
+        assert!(!match_wildcard("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn preflight_is_answered() {
+        let cors = Cors::new()
+            .with_origin("https://*.example.com")
+            .with_methods(vec![Method::Get, Method::Put])
+            .with_header("X-Token")
+            .with_max_age(600);
+        let mut req = preflight(example(), Method::Put)
+            .with_header(AccessControlRequestHeaders(vec![Ascii::new("x-token".to_owned())]));
+        let res = cors.before(&mut req).unwrap().unwrap();
+        assert_eq!(res.status(), StatusCode::NoContent);
+        assert_eq!(allow_origin(res.headers()),
+            Some(&AccessControlAllowOrigin::Value("https://app.example.com".to_owned())));
+        assert_eq!(res.header::<AccessControlAllowMethods>(),
+            Some(&AccessControlAllowMethods(vec![Method::Get, Method::Put])));
+        assert_eq!(res.header::<AccessControlAllowHeaders>(),
+            Some(&AccessControlAllowHeaders(vec![Ascii::new("X-Token".to_owned())])));
+        assert_eq!(res.header::<AccessControlMaxAge>(), Some(&AccessControlMaxAge(600)));
+        assert_eq!(res.header::<Vary>(),
+            Some(&Vary::Items(vec![Ascii::new("Origin".to_owned())])));
+        assert!(!res.headers().has::<AccessControlAllowCredentials>());
+    }
+    #[test]
+    fn preflight_echoes_headers_if_not_restricted() {
+        let cors = Cors::new();
+        let requested = vec![Ascii::new("X-Anything".to_owned())];
+        let mut req = preflight(example(), Method::Get)
+            .with_header(AccessControlRequestHeaders(requested.clone()));
+        let res = cors.before(&mut req).unwrap().unwrap();
+        assert_eq!(allow_origin(res.headers()), Some(&AccessControlAllowOrigin::Any));
+        assert_eq!(res.header::<AccessControlAllowHeaders>(),
+            Some(&AccessControlAllowHeaders(requested)));
+    }
+    #[test]
+    fn preflight_is_rejected() {
+        let cors = Cors::new()
+            .with_origin("https://*.example.com")
+            .with_methods(vec![Method::Get])
+            .with_header("X-Token");
+        let status = |mut req: Request| cors.before(&mut req).unwrap_err().status();
+        assert_eq!(status(preflight(evil(), Method::Get)), StatusCode::Forbidden);
+        assert_eq!(status(preflight(example(), Method::Delete)), StatusCode::Forbidden);
+        let req = preflight(example(), Method::Get)
+            .with_header(AccessControlRequestHeaders(vec![Ascii::new("X-Other".to_owned())]));
+        assert_eq!(status(req), StatusCode::Forbidden);
+    }
+    #[test]
+    fn actual_requests_pass_through() {
+        let cors = Cors::new();
+        // `OPTIONS` without `Access-Control-Request-Method` is not a preflight.
+        let mut req = request(Method::Options, example());
+        assert!(cors.before(&mut req).unwrap().is_none());
+        let mut req = request(Method::Delete, evil());
+        assert!(cors.before(&mut req).unwrap().is_none());
+    }
+
+    #[test]
+    fn responses_and_errors_are_decorated() {
+        let cors = Cors::new()
+            .with_origin("https://app.example.com")
+            .with_exposed_header("X-Total")
+            .with_credentials(true);
+        let req = request(Method::Get, example());
+        let res = decorate(&cors, &req);
+        assert_eq!(allow_origin(res.headers()),
+            Some(&AccessControlAllowOrigin::Value("https://app.example.com".to_owned())));
+        assert!(res.headers().has::<AccessControlAllowCredentials>());
+        assert_eq!(res.header::<AccessControlExposeHeaders>(),
+            Some(&AccessControlExposeHeaders(vec![Ascii::new("X-Total".to_owned())])));
+        let err = cors.error(&req, Error::not_found("Not found.")).unwrap_err();
+        assert_eq!(allow_origin(&err.headers()),
+            Some(&AccessControlAllowOrigin::Value("https://app.example.com".to_owned())));
+        assert!(err.headers().has::<AccessControlAllowCredentials>());
+        // Disallowed origins get nothing.
+        let res = decorate(&cors, &request(Method::Get, evil()));
+        assert!(allow_origin(res.headers()).is_none());
+        assert!(!res.headers().has::<AccessControlAllowCredentials>());
+    }
+    #[test]
+    fn vary_is_extended() {
+        let cors = Cors::new()
+            .with_origin("https://app.example.com");
+        let req = request(Method::Get, example());
+        let res = Response::new()
+            .with_header(Vary::Items(vec![Ascii::new("Accept".to_owned())]));
+        let res = cors.after(&req, res).unwrap();
+        assert_eq!(res.header::<Vary>(), Some(&Vary::Items(vec![
+            Ascii::new("Accept".to_owned()),
+            Ascii::new("Origin".to_owned()),
+        ])));
+    }
+    #[test]
+    fn credentials_need_explicit_origins() {
+        let cors = Cors::new()
+            .with_credentials(true);
+        let res = decorate(&cors, &request(Method::Get, evil()));
+        assert_eq!(allow_origin(res.headers()), Some(&AccessControlAllowOrigin::Any));
+        assert!(!res.headers().has::<AccessControlAllowCredentials>());
+    }
+    #[test]
+    fn null_origin_has_no_credentials() {
+        let cors = Cors::new()
+            .with_origin("*")
+            .with_credentials(true);
+        let res = decorate(&cors, &request(Method::Get, Origin::null()));
+        assert!(allow_origin(res.headers()).is_none());
+        assert!(!res.headers().has::<AccessControlAllowCredentials>());
+        let mut req = preflight(Origin::null(), Method::Get);
+        assert!(cors.before(&mut req).is_err());
+        // Without credentials, it's as safe as the others.
+        let cors = Cors::new()
+            .with_origin("*");
+        let res = decorate(&cors, &request(Method::Get, Origin::null()));
+        assert_eq!(allow_origin(res.headers()),
+            Some(&AccessControlAllowOrigin::Value("null".to_owned())));
+    }
+}
@@ -1,5 +1,15 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::error::Error as StdError;
+use std::io::Error as IoError;
+use std::num::{ParseFloatError, ParseIntError};
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use serde::Serialize;
+use serde_json::{Map, Value};
 use proto::HyperResponse;
+use render::ErrorRenderer;
 use prelude::*;
 use self::header::Header;
 
@@ -12,102 +22,401 @@ use self::header::Header;
 /// information about the error occured in the current transaction. The error
 /// types are customizable. Error types are defined as HTTP status codes
 /// (>= 400). Every error realized by Writium will be logged.
+///
+/// The description can be either static or built at runtime, e.g.,
+/// `format!("Unable to find book {}.", isbn)`. Structured detail can be
+/// attached to tell clients more about the error, like which field is invalid;
+/// it's included in the response body.
+///
+/// `Error` is `Send` and `Sync`, so are its causes. Common errors like
+/// `io::Error` and `serde_json::Error` can be converted into `Error`, so they
+/// can be propagated with `?`. Text and numbers are considered client input,
+/// so they are client errors; I/O and JSON errors are server errors, whose
+/// reasons are not exposed.
+///
+/// # Response Body
+///
+/// Errors are rendered as problem details (RFC 7807) of type
+/// `application/problem+json` by default:
+///
+/// * `type` is a URI identifying the problem type, `about:blank` by default;
+/// * `title` is a summary of the problem type, the reason phrase of status by
+///   default;
+/// * `status` is the status code;
+/// * `detail` is the description;
+/// * `instance` is a URI identifying the occurrence, which `Writium` sets to
+///   the request path by default.
+///
+/// Members of the structured detail are extension members of the problem
+/// details, if it's a JSON object; otherwise it's the `extra` member.
+///
+/// Errors can also be rendered in the legacy form `{"msg": ..., "detail":
+/// ...}`, where `detail` is the structured detail. See `ErrorFormat`.
+///
+/// `Writium` renders errors with an `ErrorRenderer`, which can produce other
+/// types of body as well. See module `render` for more information.
 pub struct Error {
-    headers: Headers,
+    // `Headers` caches typed headers in cells, so it's not `Sync` itself.
+    headers: Mutex<Headers>,
     status: StatusCode,
-    description: &'static str,
-    cause: Option<Box<::std::error::Error>>,
+    description: Cow<'static, str>,
+    detail: Option<Value>,
+    ext: Option<Box<Extension>>,
+    cause: Option<Box<dyn StdError + Send + Sync>>,
+}
+/// Properties rarely given. They are boxed to keep `Error` small.
+#[derive(Default)]
+struct Extension {
+    problem_type: Option<Cow<'static, str>>,
+    title: Option<Cow<'static, str>>,
+    instance: Option<String>,
+    renderer: Option<Arc<dyn ErrorRenderer>>,
 }
 impl Error {
-    pub fn new(status: StatusCode, description: &'static str) -> Error {
+    pub fn new<D>(status: StatusCode, description: D) -> Error
+        where D: Into<Cow<'static, str>> {
         Error {
-            status: status,
-            headers: Headers::default(),
-            description: description,
+            status,
+            headers: Mutex::default(),
+            description: description.into(),
+            detail: None,
+            ext: None,
             cause: None,
         }
     }
     pub fn status(&self) -> StatusCode {
         self.status
     }
+    /// Get the description of error.
+    pub fn message(&self) -> &str {
+        &self.description
+    }
+    /// Get the structured detail of error, if any.
+    pub fn detail(&self) -> Option<&Value> {
+        self.detail.as_ref()
+    }
+    /// Get the chain of causes, from the direct cause to the root cause.
+    pub fn causes(&self) -> Causes<'_> {
+        Causes(self.source())
+    }
+    /// Get the URI identifying the problem type, if any.
+    pub fn problem_type(&self) -> Option<&str> {
+        self.ext.as_ref().and_then(|x| x.problem_type.as_deref())
+    }
+    /// Get the summary of the problem type, if any.
+    pub fn title(&self) -> Option<&str> {
+        self.ext.as_ref().and_then(|x| x.title.as_deref())
+    }
+    /// Get the URI identifying the occurrence of problem, if any.
+    pub fn instance(&self) -> Option<&str> {
+        self.ext.as_ref().and_then(|x| x.instance.as_deref())
+    }
+    fn ext_mut(&mut self) -> &mut Extension {
+        self.ext.get_or_insert_with(Default::default)
+    }
 
-    pub fn internal(description: &'static str) -> Error {
+    pub fn internal<D: Into<Cow<'static, str>>>(description: D) -> Error {
         Error::new(StatusCode::InternalServerError, description)
     }
-    pub fn unauthorized(description: &'static str) -> Error {
+    pub fn unauthorized<D: Into<Cow<'static, str>>>(description: D) -> Error {
         Error::new(StatusCode::Unauthorized, description)
     }
-    pub fn bad_request(description: &'static str) -> Error {
+    pub fn bad_request<D: Into<Cow<'static, str>>>(description: D) -> Error {
         Error::new(StatusCode::BadRequest, description)
     }
-    pub fn forbidden(description: &'static str) -> Error {
+    pub fn forbidden<D: Into<Cow<'static, str>>>(description: D) -> Error {
         Error::new(StatusCode::Forbidden, description)
     }
-    pub fn not_found(description: &'static str) -> Error {
+    pub fn not_found<D: Into<Cow<'static, str>>>(description: D) -> Error {
         Error::new(StatusCode::NotFound, description)
     }
+    pub fn unprocessable_entity<D: Into<Cow<'static, str>>>(description: D) -> Error {
+        Error::new(StatusCode::UnprocessableEntity, description)
+    }
+    pub fn payload_too_large<D: Into<Cow<'static, str>>>(description: D) -> Error {
+        Error::new(StatusCode::PayloadTooLarge, description)
+    }
+    pub fn service_unavailable<D: Into<Cow<'static, str>>>(description: D) -> Error {
+        Error::new(StatusCode::ServiceUnavailable, description)
+    }
     pub fn method_not_allowed() -> Error {
-        Error::new(StatusCode::NotFound, "Method is not allowed.")
+        Error::new(StatusCode::MethodNotAllowed, "Method is not allowed.")
+    }
+
+    /// Get a copy of a specific header. Headers are kept behind a lock, as
+    /// `Headers` is not thread-safe, so only copies can be given.
+    pub fn header<H: Header + Clone>(&self) -> Option<H> {
+        self.lock_headers().get::<H>().cloned()
+    }
+    /// Get a copy of all the headers.
+    pub fn headers(&self) -> Headers {
+        self.lock_headers().clone()
+    }
+    /// Get all the headers for modification.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        self.headers.get_mut().unwrap_or_else(|err| err.into_inner())
+    }
+    fn lock_headers(&self) -> MutexGuard<'_, Headers> {
+        self.headers.lock().unwrap_or_else(|err| err.into_inner())
     }
 
     pub fn set_header<H: Header>(&mut self, header: H) {
-        self.headers.set(header);
+        self.headers_mut().set(header);
     }
     pub fn set_headers(&mut self, headers: Headers) {
-        self.headers = headers;
+        *self.headers_mut() = headers;
     }
     pub fn set_cause<E>(&mut self, err: E)
-        where E: 'static + ::std::error::Error {
+        where E: 'static + StdError + Send + Sync {
         self.cause = Some(Box::new(err));
     }
-    
+    /// Set the URI identifying the occurrence of problem.
+    pub fn set_instance(&mut self, instance: &str) {
+        self.ext_mut().instance = Some(instance.to_owned());
+    }
+    /// Let the given renderer render the error, unless a renderer has been
+    /// chosen.
+    pub(crate) fn set_default_renderer(&mut self, renderer: &Arc<dyn ErrorRenderer>) {
+        let ext = self.ext_mut();
+        if ext.renderer.is_none() {
+            ext.renderer = Some(renderer.clone());
+        }
+    }
+    pub(crate) fn take_renderer(&mut self) -> Option<Arc<dyn ErrorRenderer>> {
+        self.ext.as_mut().and_then(|x| x.renderer.take())
+    }
+    /// Set structured detail of error. Detail failed to serialize is dropped.
+    pub fn set_detail<T: Serialize>(&mut self, detail: T) {
+        match ::serde_json::to_value(detail) {
+            Ok(detail) => self.detail = Some(detail),
+            Err(err) => warn!("Unable to serialize error detail: {}", err),
+        }
+    }
+
 
     pub fn with_header<H: Header>(mut self, header: H) -> Self {
-        self.headers.set(header);
+        self.set_header(header);
         self
     }
     pub fn with_headers(mut self, headers: Headers) -> Self {
-        self.headers = headers;
+        self.set_headers(headers);
         self
     }
     pub fn with_cause<E>(mut self, err: E) -> Self
-        where E: 'static + ::std::error::Error {
+        where E: 'static + StdError + Send + Sync {
         self.cause = Some(Box::new(err));
         self
     }
+    pub fn with_detail<T: Serialize>(mut self, detail: T) -> Self {
+        self.set_detail(detail);
+        self
+    }
+    pub fn with_problem_type<T>(mut self, problem_type: T) -> Self
+        where T: Into<Cow<'static, str>> {
+        self.ext_mut().problem_type = Some(problem_type.into());
+        self
+    }
+    pub fn with_title<T>(mut self, title: T) -> Self
+        where T: Into<Cow<'static, str>> {
+        self.ext_mut().title = Some(title.into());
+        self
+    }
+    pub fn with_instance(mut self, instance: &str) -> Self {
+        self.set_instance(instance);
+        self
+    }
+
+    /// Render the error as problem details.
+    pub fn to_problem(&self) -> Value {
+        let mut rv = match self.detail {
+            Some(Value::Object(ref members)) => members.clone(),
+            Some(ref extra) => {
+                let mut members = Map::new();
+                members.insert("extra".to_owned(), extra.clone());
+                members
+            },
+            None => Map::new(),
+        };
+        let problem_type = self.problem_type().unwrap_or("about:blank");
+        let title = self.title()
+            .or_else(|| self.status.canonical_reason())
+            .unwrap_or_default();
+        rv.insert("type".to_owned(), json!(problem_type));
+        rv.insert("title".to_owned(), json!(title));
+        rv.insert("status".to_owned(), json!(self.status.as_u16()));
+        rv.insert("detail".to_owned(), json!(self.description));
+        if let Some(instance) = self.instance() {
+            rv.insert("instance".to_owned(), json!(instance));
+        }
+        Value::Object(rv)
+    }
+    /// Render the error in the legacy form.
+    pub fn to_legacy(&self) -> Value {
+        let mut rv = json!({ "msg": self.description });
+        if let Some(ref detail) = self.detail {
+            rv["detail"] = detail.clone();
+        }
+        rv
+    }
+    /// Transform the error into `HyperResponse` in the given format.
+    pub fn into_response(self, format: ErrorFormat) -> HyperResponse {
+        use hyper::header::{ContentType, ContentLength};
+        let (body, content_type) = match format {
+            ErrorFormat::Problem => (self.to_problem(), "application/problem+json"),
+            ErrorFormat::Legacy => (self.to_legacy(), "application/json; charset=UTF-8"),
+        };
+        let body = body.to_string().into_bytes();
+        let headers = self.headers.into_inner().unwrap_or_else(|err| err.into_inner());
+        HyperResponse::new()
+            .with_status(self.status)
+            .with_headers(headers)
+            .with_header(ContentType(content_type.parse().unwrap()))
+            .with_header(ContentLength(body.len() as u64))
+            .with_body(body)
+    }
 }
 impl ::std::fmt::Debug for Error {
     fn fmt(&self, f: &mut Formatter) -> FormatResult {
-        f.write_str(self.description)
+        f.write_str(&self.description)
     }
 }
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> FormatResult {
-        f.write_str(self.description)
+        f.write_str(&self.description)
     }
 }
-impl ::std::error::Error for Error {
+impl StdError for Error {
     fn description(&self) -> &str {
-        self.description
+        &self.description
     }
-    fn cause(&self) -> Option<&::std::error::Error> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self.cause {
             Some(ref e) => Some(&**e),
             None => ::std::option::Option::None,
         }
     }
 }
-impl Into<HyperResponse> for Error {
-    fn into(self) -> HyperResponse {
-        use hyper::header::{ContentType, ContentLength};
-        let body = format!(r#"{{"msg":"{}"}}"#, self.description).into_bytes();
-        HyperResponse::new()
-            .with_status(self.status)
-            .with_headers(self.headers)
-            .with_header(ContentType("application/json; charset=UTF-8".parse().unwrap()))
-            .with_header(ContentLength(body.len() as u64))
-            .with_body(body)
+impl From<Error> for HyperResponse {
+    fn from(err: Error) -> HyperResponse {
+        err.into_response(ErrorFormat::Problem)
+    }
+}
+
+// Conversions of common errors, so that they can be propagated with `?`. The
+// original errors are kept as causes.
+
+/// I/O is done by the server, e.g., reading its own files, so it's `500
+/// Internal Server Error` whatever the kind is. APIs mapping paths from clients
+/// onto files should pick client errors themselves, as `StaticFiles` does.
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Error {
+        Error::internal("I/O error occurred.").with_cause(err)
+    }
+}
+/// JSON converted here can be serialized by the server as well as deserialized
+/// from clients, so it's `500 Internal Server Error` and the reason is not
+/// exposed. Client inputs deserialized by `Request`'s `to_json()` fail with
+/// client errors instead.
+impl From<::serde_json::Error> for Error {
+    fn from(err: ::serde_json::Error) -> Error {
+        Error::internal("Unable to process JSON.").with_cause(err)
+    }
+}
+/// Text is considered client input, so it's `400 Bad Request`.
+impl From<Utf8Error> for Error {
+    fn from(err: Utf8Error) -> Error {
+        Error::bad_request("Text is not valid UTF-8.").with_cause(err)
+    }
+}
+/// Text is considered client input, so it's `400 Bad Request`.
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Error {
+        Error::bad_request("Text is not valid UTF-8.").with_cause(err)
+    }
+}
+/// Numbers are considered client input, so it's `400 Bad Request`.
+impl From<ParseIntError> for Error {
+    fn from(err: ParseIntError) -> Error {
+        let reason = err.to_string();
+        Error::bad_request("Unable to parse integer.")
+            .with_detail(json!({ "reason": reason }))
+            .with_cause(err)
+    }
+}
+/// Numbers are considered client input, so it's `400 Bad Request`.
+impl From<ParseFloatError> for Error {
+    fn from(err: ParseFloatError) -> Error {
+        let reason = err.to_string();
+        Error::bad_request("Unable to parse float.")
+            .with_detail(json!({ "reason": reason }))
+            .with_cause(err)
     }
 }
 
+/// Iterator over the chain of causes of an error. See `Error::causes()`.
+pub struct Causes<'a>(Option<&'a (dyn StdError + 'static)>);
+impl<'a> Iterator for Causes<'a> {
+    type Item = &'a (dyn StdError + 'static);
+    fn next(&mut self) -> Option<Self::Item> {
+        let rv = self.0.take()?;
+        self.0 = rv.source();
+        Some(rv)
+    }
+}
+
+/// Error of which only the message is kept. It stands for causes which are not
+/// thread-safe.
+#[derive(Debug)]
+pub(crate) struct Message(pub(crate) String);
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        f.write_str(&self.0)
+    }
+}
+impl StdError for Message {}
+
+/// Format of error response bodies. See `Error` for more information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Problem details of type `application/problem+json`. It's the default.
+    Problem,
+    /// `{"msg": ..., "detail": ...}` of type `application/json`.
+    Legacy,
+}
+
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use prelude::*;
+
+    #[test]
+    fn server_errors_hide_reasons() {
+        // Maps with non-string keys can't be serialized into JSON.
+        let mut map = BTreeMap::new();
+        map.insert(vec![1], 1);
+        let err = Error::from(::serde_json::to_string(&map).unwrap_err());
+        assert_eq!(err.status(), StatusCode::InternalServerError);
+        assert_eq!(err.detail(), None);
+        assert!(err.causes().next().is_some());
+        let err = Error::from(IoError::new(IoErrorKind::NotFound, "config.toml"));
+        assert_eq!(err.status(), StatusCode::InternalServerError);
+        assert!(!err.to_problem().to_string().contains("config.toml"));
+    }
+    #[test]
+    fn client_errors_have_reasons() {
+        let err = Error::from("x".parse::<u32>().unwrap_err());
+        assert_eq!(err.status(), StatusCode::BadRequest);
+        assert_eq!(err.detail(), Some(&json!({ "reason": "invalid digit found in string" })));
+    }
+    #[test]
+    fn headers_are_copied() {
+        use hyper::header::Allow;
+        let err = Error::method_not_allowed()
+            .with_header(Allow(vec![Method::Get]));
+        assert_eq!(err.header::<Allow>(), Some(Allow(vec![Method::Get])));
+        assert!(err.headers().has::<Allow>());
+    }
+}
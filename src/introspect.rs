@@ -0,0 +1,240 @@
+//! Introspection of binded APIs.
+//!
+//! Every API can describe itself with `Api::info()`. Namespaces describe their
+//! sub-APIs recursively, so the full route map of an application can be
+//! obtained from `Writium::routes()` and rendered as JSON or text.
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::sync::Arc;
+use serde_json::Value;
+use hyper::header::ContentType;
+use prelude::*;
+
+/// The key of extra where `Writium` puts its root namespace for the APIs in
+/// higher hierarchy to inspect.
+pub const ROOT_EXTRA_KEY: &str = "writium.root";
+
+/// Kind of a binded API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteKind {
+    /// An ordinary API whose internal behavior is unknown.
+    Api,
+    /// A `Namespace` which has sub-APIs.
+    Namespace,
+    /// A `MethodRouter`.
+    MethodRouter,
+}
+impl RouteKind {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            RouteKind::Api => "api",
+            RouteKind::Namespace => "namespace",
+            RouteKind::MethodRouter => "method_router",
+        }
+    }
+}
+
+/// Description of a binded API and all its sub-APIs.
+#[derive(Clone, Debug)]
+pub struct RouteInfo {
+    /// Name (pattern) of the API.
+    pub name: Vec<String>,
+    pub kind: RouteKind,
+    /// Methods acceptable. Empty if it's unknown.
+    pub methods: Vec<Method>,
+    /// Sub-APIs in binding order.
+    pub children: Vec<RouteInfo>,
+    /// Metadata provided by the API.
+    pub doc: Option<ApiDoc>,
+}
+impl RouteInfo {
+    pub fn new(name: &[&str], kind: RouteKind) -> RouteInfo {
+        RouteInfo {
+            name: name.iter().map(|x| (*x).to_owned()).collect(),
+            kind,
+            methods: Vec::new(),
+            children: Vec::new(),
+            doc: None,
+        }
+    }
+    pub fn with_methods(mut self, methods: Vec<Method>) -> RouteInfo {
+        self.methods = methods;
+        self
+    }
+    pub fn with_children(mut self, children: Vec<RouteInfo>) -> RouteInfo {
+        self.children = children;
+        self
+    }
+    pub fn with_doc(mut self, doc: Option<ApiDoc>) -> RouteInfo {
+        self.doc = doc;
+        self
+    }
+
+    /// An API with empty name is a fuse. See `Api::name()`.
+    pub fn is_fuse(&self) -> bool {
+        self.name.is_empty()
+    }
+    /// Name of the API joined as a path. It doesn't include the names of APIs
+    /// in lower hierarchy.
+    pub fn path(&self) -> String {
+        join_path(&self.name)
+    }
+
+    /// Render the route tree as JSON. Each API is rendered with the full path
+    /// it's mounted at, i.e., the names of all the APIs in lower hierarchy
+    /// joined.
+    pub fn to_json(&self) -> Value {
+        self.render_json(&[])
+    }
+    fn render_json(&self, prefix: &[String]) -> Value {
+        let full_name = self.full_name(prefix);
+        let methods: Vec<String> = self.methods.iter()
+            .map(|x| x.to_string())
+            .collect();
+        let children: Vec<Value> = self.children.iter()
+            .map(|x| x.render_json(&full_name))
+            .collect();
+        json!({
+            "name": self.name,
+            "path": join_path(&full_name),
+            "kind": self.kind.as_str(),
+            "fuse": self.is_fuse(),
+            "methods": methods,
+            "children": children,
+        })
+    }
+    /// Render the route tree as indented text, one API per line. Each API is
+    /// rendered with the full path it's mounted at, as in `to_json()`.
+    pub fn to_text(&self) -> String {
+        let mut rv = String::new();
+        self.render_text(&[], 0, &mut rv);
+        rv
+    }
+    fn render_text(&self, prefix: &[String], depth: usize, out: &mut String) {
+        let full_name = self.full_name(prefix);
+        for _ in 0..depth {
+            out.push_str("    ");
+        }
+        out.push_str(&join_path(&full_name));
+        if self.is_fuse() && depth > 0 {
+            out.push_str(" (fuse)");
+        }
+        out.push(' ');
+        out.push_str(self.kind.as_str());
+        if !self.methods.is_empty() {
+            let methods: Vec<String> = self.methods.iter()
+                .map(|x| x.to_string())
+                .collect();
+            out.push_str(&format!(" [{}]", methods.join(", ")));
+        }
+        out.push('\n');
+        for child in self.children.iter() {
+            child.render_text(&full_name, depth + 1, out);
+        }
+    }
+    /// Name of the API following the names in lower hierarchy.
+    fn full_name(&self, prefix: &[String]) -> Vec<String> {
+        let mut rv = prefix.to_vec();
+        rv.extend(self.name.iter().cloned());
+        rv
+    }
+}
+impl Display for RouteInfo {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        f.write_str(&self.to_text())
+    }
+}
+
+fn join_path(name: &[String]) -> String {
+    format!("/{}", name.join("/"))
+}
+
+/// `RouteMap` is an end-point API responding with the route map of the whole
+/// application it's binded to. The route map is rendered as JSON by default,
+/// or as text if query `format=text` is given.
+///
+/// It only works when requests are routed by `Writium`.
+pub struct RouteMap {
+    name: &'static [&'static str],
+}
+impl RouteMap {
+    pub fn new(name: &'static [&'static str]) -> RouteMap {
+        RouteMap {
+            name,
+        }
+    }
+}
+impl Api for RouteMap {
+    fn name(&self) -> &[&str] {
+        self.name
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        use std::collections::BTreeMap;
+        let info = req.extra::<Arc<Namespace>>(ROOT_EXTRA_KEY)
+            .ok_or_else(|| Error::internal("Root namespace is not available."))?
+            .info();
+        let param = req.to_param::<BTreeMap<String, String>>()?;
+        if param.get("format").map(|x| x.as_str()) == Some("text") {
+            let res = Response::new()
+                .with_header(ContentType::plaintext())
+                .with_body(info.to_text());
+            Ok(res)
+        } else {
+            Response::new()
+                .with_json(&info.to_json())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prelude::*;
+
+    struct Fuse;
+    impl Api for Fuse {
+        fn name(&self) -> &[&str] {
+            &[]
+        }
+        fn route(&self, _req: &mut Request) -> ApiResult {
+            Ok(Response::new())
+        }
+    }
+
+    fn routes() -> RouteInfo {
+        let books = MethodRouter::new(&["books", ":id"])
+            .with_get(|_| Ok(Response::new()))
+            .with_delete(|_| Ok(Response::new()));
+        let api = Namespace::new(&["api", "v1"])
+            .with_api(books)
+            .with_api(Fuse);
+        Namespace::new(&[])
+            .with_api(api)
+            .info()
+    }
+
+    #[test]
+    fn text_has_full_paths() {
+        assert_eq!(routes().to_text(), "\
+/ namespace
+    /api/v1 namespace
+        /api/v1/books/:id method_router [GET, DELETE, HEAD, OPTIONS]
+        /api/v1 (fuse) api
+");
+    }
+    #[test]
+    fn json_has_full_paths() {
+        let json = routes().to_json();
+        let api = &json["children"][0];
+        assert_eq!(json["path"], "/");
+        assert_eq!(api["name"], json!(["api", "v1"]));
+        assert_eq!(api["path"], "/api/v1");
+        assert_eq!(api["kind"], "namespace");
+        let books = &api["children"][0];
+        assert_eq!(books["path"], "/api/v1/books/:id");
+        assert_eq!(books["kind"], "method_router");
+        assert_eq!(books["methods"], json!(["GET", "DELETE", "HEAD", "OPTIONS"]));
+        let fuse = &api["children"][1];
+        assert_eq!(fuse["path"], "/api/v1");
+        assert_eq!(fuse["fuse"], true);
+        assert_eq!(fuse["kind"], "api");
+    }
+}
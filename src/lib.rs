@@ -22,28 +22,57 @@
 //! write something stream-based. Then you can add it to somewhere in your same
 //! application; you don't need to port codes to another web framework simply
 //! because it doesn't support stream-based interaction.
+extern crate base64;
+extern crate bytes;
+#[macro_use]
 pub extern crate futures;
 pub extern crate hyper;
+extern crate futures_cpupool;
 #[macro_use]
 extern crate log;
+extern crate mime_guess;
+extern crate percent_encoding;
+extern crate sha1;
+extern crate tokio_io;
+extern crate unicase;
 extern crate serde;
+extern crate serde_ignored;
+extern crate serde_path_to_error;
 extern crate serde_qs;
+#[macro_use]
 extern crate serde_json;
 
 // Writium.
 mod writium;
+pub mod pool;
 
 pub use writium::Writium;
 
 // Api and namespace.
 pub mod api;
 pub mod namespace;
+pub mod method_router;
+pub mod introspect;
+pub mod openapi;
+pub mod middleware;
+pub mod cors;
+pub mod sse;
+pub mod static_files;
+pub mod websocket;
+pub mod pattern;
+mod route_tree;
 
 // Request flow protocol.
 pub mod proto;
 
 // Error handling.
 pub mod error;
+pub mod context;
+pub mod render;
+pub mod observe;
+
+// Testing.
+pub mod testing;
 
 // Prelude.
 pub mod prelude;
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+use hyper::header::Allow;
+use openapi::Operation;
+use prelude::*;
+
+type Handler = dyn Fn(&mut Request) -> ApiResult + Send + Sync;
+
+/// `MethodRouter` is an end-point API dispatching requests to handlers by HTTP
+/// method.
+///
+/// If there is no handler registered for the method of an incoming request, a
+/// `405 Method Not Allowed` error is returned with an `Allow` header listing
+/// all the acceptable methods. `OPTIONS` requests are answered automatically
+/// with the same header, unless a handler is registered for `OPTIONS`
+/// explicitly. Likewise, `HEAD` requests are handled by the `GET` handler,
+/// whose response body is stripped by `Writium`.
+#[derive(Clone)]
+pub struct MethodRouter {
+    name: &'static [&'static str],
+    handlers: Vec<(Method, Arc<Handler>)>,
+    doc: ApiDoc,
+}
+impl MethodRouter {
+    pub fn new(name: &'static [&'static str]) -> MethodRouter {
+        MethodRouter {
+            name,
+            handlers: Vec::new(),
+            doc: ApiDoc::new(),
+        }
+    }
+
+    /// Register a handler for the given method. Handler registered later
+    /// replaces the former one of the same method.
+    pub fn bind<F>(&mut self, method: Method, handler: F)
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        let handler = Arc::new(handler) as Arc<Handler>;
+        if let Some(pos) = self.handlers.iter().position(|x| x.0 == method) {
+            self.handlers[pos].1 = handler;
+        } else {
+            self.handlers.push((method, handler));
+        }
+    }
+    pub fn with_method<F>(mut self, method: Method, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.bind(method, handler);
+        self
+    }
+    pub fn with_get<F>(self, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.with_method(Method::Get, handler)
+    }
+    pub fn with_post<F>(self, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.with_method(Method::Post, handler)
+    }
+    pub fn with_put<F>(self, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.with_method(Method::Put, handler)
+    }
+    pub fn with_patch<F>(self, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.with_method(Method::Patch, handler)
+    }
+    pub fn with_delete<F>(self, handler: F) -> MethodRouter
+        where F: 'static + Fn(&mut Request) -> ApiResult + Send + Sync {
+        self.with_method(Method::Delete, handler)
+    }
+
+    /// Set the summary and description of the router. See module `openapi`.
+    pub fn with_doc(mut self, doc: ApiDoc) -> MethodRouter {
+        let ops = ::std::mem::take(&mut self.doc.operations);
+        self.doc = doc;
+        self.doc.operations.extend(ops);
+        self
+    }
+    /// Document the operation of a method. See module `openapi`.
+    pub fn with_operation(mut self, op: Operation) -> MethodRouter {
+        self.doc.operations.push(op);
+        self
+    }
+
+    /// Methods acceptable to this router, in registration order. `OPTIONS` is
+    /// always included; `HEAD` is included if `GET` is.
+    pub fn methods(&self) -> Vec<Method> {
+        let mut rv: Vec<Method> = self.handlers.iter()
+            .map(|x| x.0.clone())
+            .collect();
+        if rv.contains(&Method::Get) && !rv.contains(&Method::Head) {
+            rv.push(Method::Head);
+        }
+        if !rv.contains(&Method::Options) {
+            rv.push(Method::Options);
+        }
+        rv
+    }
+}
+impl Api for MethodRouter {
+    fn name(&self) -> &[&str] {
+        self.name
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let method = req.method();
+        let handler = self.handlers.iter()
+            .find(|x| x.0 == method)
+            .or_else(|| match method {
+                Method::Head => self.handlers.iter().find(|x| x.0 == Method::Get),
+                _ => None,
+            });
+        if let Some((_, handler)) = handler {
+            return handler(req)
+        }
+        if method == Method::Options {
+            let res = Response::new()
+                .with_status(StatusCode::NoContent)
+                .with_header(Allow(self.methods()));
+            Ok(res)
+        } else {
+            let err = Error::method_not_allowed()
+                .with_header(Allow(self.methods()));
+            Err(err)
+        }
+    }
+    fn info(&self) -> RouteInfo {
+        RouteInfo::new(self.name, RouteKind::MethodRouter)
+            .with_methods(self.methods())
+            .with_doc(Some(self.doc.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::Allow;
+    use prelude::*;
+    use testing::TestClient;
+    use super::MethodRouter;
+
+    fn router() -> MethodRouter {
+        MethodRouter::new(&["books"])
+            .with_get(|_| Ok(Response::new().with_body("list")))
+            .with_post(|_| Ok(Response::new().with_status(StatusCode::Created)))
+    }
+
+    #[test]
+    fn methods_are_dispatched() {
+        let res = router().route(&mut Request::new(Method::Get)).unwrap();
+        assert_eq!(res.body(), b"list");
+        let res = router().route(&mut Request::new(Method::Post)).unwrap();
+        assert_eq!(res.status(), StatusCode::Created);
+    }
+    #[test]
+    fn other_methods_are_not_allowed() {
+        let err = router().route(&mut Request::new(Method::Delete)).unwrap_err();
+        assert_eq!(err.status(), StatusCode::MethodNotAllowed);
+        let allow = vec![Method::Get, Method::Post, Method::Head, Method::Options];
+        assert_eq!(err.headers().get::<Allow>(), Some(&Allow(allow)));
+    }
+    #[test]
+    fn options_are_answered() {
+        let res = router().route(&mut Request::new(Method::Options)).unwrap();
+        assert_eq!(res.status(), StatusCode::NoContent);
+        let allow = vec![Method::Get, Method::Post, Method::Head, Method::Options];
+        assert_eq!(res.header::<Allow>(), Some(&Allow(allow)));
+        // Explicit handlers take precedence.
+        let router = router()
+            .with_method(Method::Options, |_| Ok(Response::new().with_body("custom")));
+        let res = router.route(&mut Request::new(Method::Options)).unwrap();
+        assert_eq!(res.body(), b"custom");
+    }
+    #[test]
+    fn head_falls_back_to_get() {
+        let res = router().route(&mut Request::new(Method::Head)).unwrap();
+        assert_eq!(res.body(), b"list");
+        let router = MethodRouter::new(&["books"])
+            .with_post(|_| Ok(Response::new()));
+        let err = router.route(&mut Request::new(Method::Head)).unwrap_err();
+        assert_eq!(err.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(err.headers().get::<Allow>(), Some(&Allow(vec![Method::Post, Method::Options])));
+    }
+    #[test]
+    fn head_is_routed_as_get_for_any_api() {
+        struct Plain;
+        impl Api for Plain {
+            fn name(&self) -> &[&str] {
+                &["plain", ":id"]
+            }
+            fn route(&self, req: &mut Request) -> ApiResult {
+                match req.method() {
+                    Method::Get => Ok(Response::new()
+                        .with_body(format!("plain {}", req.param("id").unwrap()))),
+                    _ => Err(Error::method_not_allowed()
+                        .with_header(Allow(vec![Method::Get]))),
+                }
+            }
+        }
+        let client = TestClient::for_api(Plain);
+        client.head("/plain/1").send()
+            .assert_status(StatusCode::Ok)
+            .assert_raw_header("Content-Length", "7")
+            .assert_body("");
+        client.post("/plain/1").send()
+            .assert_status(StatusCode::MethodNotAllowed);
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+use futures::{future, Future};
+use api::RouteFuture;
+use prelude::*;
+
+/// # Writium Middleware
+///
+/// `Middleware` wraps the routing of a `Namespace` (or `Writium`) and provides
+/// cross-cutting functionalities like authentication, logging and decoration of
+/// headers.
+///
+/// # Composition
+///
+/// Middlewares are composed like an onion. `before()` of middlewares are called
+/// in attaching order before the request is routed; then `after()` or
+/// `error()` are called in reverse order, depending on whether the routing (or
+/// the middleware inside) succeeded. Once `before()` short-circuits, no inner
+/// middleware nor API is called, and the output is passed to the outer
+/// middlewares only, i.e., `after()` or `error()` of the short-circuiting
+/// middleware itself are not called.
+///
+/// Errors occurred before routing, e.g., invalid paths and request bodies too
+/// large, are passed to `error()` of all the middlewares without calling
+/// `before()`, as the request is already rejected.
+pub trait Middleware: 'static + Send + Sync {
+    /// Called before the request is routed. Return a `Response` or an `Error`
+    /// to short-circuit the routing; return `Ok(None)` to continue.
+    fn before(&self, _req: &mut Request) -> Result<Option<Response>> {
+        Ok(None)
+    }
+    /// Called when a response is successfully made. Returning an `Error` will
+    /// let the outer middlewares see an error.
+    fn after(&self, _req: &Request, res: Response) -> ApiResult {
+        Ok(res)
+    }
+    /// Called when an error occurred. Returning a `Response` will recover from
+    /// the error for the outer middlewares.
+    fn error(&self, _req: &Request, err: Error) -> ApiResult {
+        Err(err)
+    }
+}
+
+/// Call `before()` of middlewares. The number of middlewares entered and the
+/// short-circuiting result, if any, are returned.
+fn enter(middlewares: &[Arc<dyn Middleware>], req: &mut Request)
+    -> (usize, Option<ApiResult>) {
+    for (i, middleware) in middlewares.iter().enumerate() {
+        match middleware.before(req) {
+            Ok(None) => {},
+            Ok(Some(x)) => return (i, Some(Ok(x))),
+            Err(err) => return (i, Some(Err(err))),
+        }
+    }
+    (middlewares.len(), None)
+}
+/// Call `after()` or `error()` of the entered middlewares in reverse order.
+fn leave(entered: &[Arc<dyn Middleware>], req: &Request, mut res: ApiResult)
+    -> ApiResult {
+    for middleware in entered.iter().rev() {
+        res = match res {
+            Ok(x) => middleware.after(req, x),
+            Err(err) => middleware.error(req, err),
+        };
+    }
+    res
+}
+
+/// Route a request through the given middlewares. `route` is called if no
+/// middleware short-circuited.
+pub(crate) fn run<F>(middlewares: &[Arc<dyn Middleware>], req: &mut Request,
+    route: F) -> ApiResult
+    where F: FnOnce(&mut Request) -> ApiResult {
+    let (entered, res) = enter(middlewares, req);
+    let res = res.unwrap_or_else(|| route(req));
+    leave(&middlewares[..entered], req, res)
+}
+/// Pass an error occurred before routing through the given middlewares. `route`
+/// is called in place of the routing, without calling `before()`.
+pub(crate) fn run_error<F>(middlewares: &[Arc<dyn Middleware>], req: &mut Request,
+    route: F) -> ApiResult
+    where F: FnOnce(&mut Request) -> ApiResult {
+    let res = route(req);
+    leave(middlewares, req, res)
+}
+/// Route a request through the given middlewares asynchronously. `route` is
+/// called if no middleware short-circuited.
+pub(crate) fn run_async<F>(middlewares: &[Arc<dyn Middleware>], mut req: Request,
+    route: F) -> RouteFuture
+    where F: FnOnce(Request) -> RouteFuture {
+    let (entered, res) = enter(middlewares, &mut req);
+    let f_res = match res {
+        Some(res) => Box::new(future::ok((req, res))),
+        None => route(req),
+    };
+    if entered == 0 {
+        return f_res
+    }
+    let entered = middlewares[..entered].to_vec();
+    let f_res = f_res.map(move |(req, res)| {
+        let res = leave(&entered, &req, res);
+        (req, res)
+    });
+    Box::new(f_res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use futures::Future;
+    use prelude::*;
+    use super::{run, run_async, run_error};
+
+    type Trace = Arc<Mutex<Vec<String>>>;
+
+    /// Record the hooks called and act as configured.
+    struct Layer {
+        name: &'static str,
+        trace: Trace,
+        short_circuit: Option<bool>,
+        fail_after: bool,
+        recover: bool,
+    }
+    impl Layer {
+        fn new(name: &'static str, trace: &Trace) -> Layer {
+            Layer {
+                name,
+                trace: trace.clone(),
+                short_circuit: None,
+                fail_after: false,
+                recover: false,
+            }
+        }
+        fn log(&self, hook: &str) {
+            self.trace.lock().unwrap().push(format!("{}.{}", self.name, hook));
+        }
+    }
+    impl Middleware for Layer {
+        fn before(&self, _req: &mut Request) -> Result<Option<Response>> {
+            self.log("before");
+            match self.short_circuit {
+                Some(true) => Ok(Some(Response::new().with_status(StatusCode::Accepted))),
+                Some(false) => Err(Error::unauthorized("Denied.")),
+                None => Ok(None),
+            }
+        }
+        fn after(&self, _req: &Request, res: Response) -> ApiResult {
+            self.log("after");
+            if self.fail_after {
+                Err(Error::internal("Failed."))
+            } else {
+                Ok(res)
+            }
+        }
+        fn error(&self, _req: &Request, err: Error) -> ApiResult {
+            self.log("error");
+            if self.recover {
+                Ok(Response::new().with_status(StatusCode::Gone))
+            } else {
+                Err(err)
+            }
+        }
+    }
+
+    fn middlewares(layers: Vec<Layer>) -> Vec<Arc<dyn Middleware>> {
+        layers.into_iter()
+            .map(|x| Arc::new(x) as Arc<dyn Middleware>)
+            .collect()
+    }
+    /// Route through the middlewares both synchronously and asynchronously,
+    /// and check that the traces and statuses are the same.
+    fn route(make: &dyn Fn(&Trace) -> Vec<Layer>, api_res: fn() -> ApiResult)
+        -> (Vec<String>, StatusCode) {
+        let status = |res: ApiResult| match res {
+            Ok(res) => res.status(),
+            Err(err) => err.status(),
+        };
+        let trace = Trace::default();
+        let mut req = Request::new(Method::Get);
+        let res = run(&middlewares(make(&trace)), &mut req, |_| {
+            trace.lock().unwrap().push("api".to_owned());
+            api_res()
+        });
+        let sync = (trace.lock().unwrap().clone(), status(res));
+
+        let trace = Trace::default();
+        let api_trace = trace.clone();
+        let f_res = run_async(&middlewares(make(&trace)), Request::new(Method::Get), move |req| {
+            api_trace.lock().unwrap().push("api".to_owned());
+            Box::new(::futures::future::ok((req, api_res())))
+        });
+        let (_, res) = f_res.wait().unwrap();
+        let async_ = (trace.lock().unwrap().clone(), status(res));
+        assert_eq!(sync, async_);
+        sync
+    }
+
+    #[test]
+    fn onion_order() {
+        let (trace, status) = route(&|trace| vec![
+            Layer::new("outer", trace),
+            Layer::new("inner", trace),
+        ], || Ok(Response::new()));
+        assert_eq!(trace, ["outer.before", "inner.before", "api", "inner.after", "outer.after"]);
+        assert_eq!(status, StatusCode::Ok);
+    }
+    #[test]
+    fn short_circuit_with_response() {
+        let (trace, status) = route(&|trace| {
+            let mut inner = Layer::new("inner", trace);
+            inner.short_circuit = Some(true);
+            vec![Layer::new("outer", trace), inner, Layer::new("innermost", trace)]
+        }, || Ok(Response::new()));
+        assert_eq!(trace, ["outer.before", "inner.before", "outer.after"]);
+        assert_eq!(status, StatusCode::Accepted);
+    }
+    #[test]
+    fn short_circuit_with_error() {
+        let (trace, status) = route(&|trace| {
+            let mut inner = Layer::new("inner", trace);
+            inner.short_circuit = Some(false);
+            vec![Layer::new("outer", trace), inner]
+        }, || Ok(Response::new()));
+        assert_eq!(trace, ["outer.before", "inner.before", "outer.error"]);
+        assert_eq!(status, StatusCode::Unauthorized);
+    }
+    #[test]
+    fn errors_pass_outwards() {
+        let (trace, status) = route(&|trace| vec![
+            Layer::new("outer", trace),
+            Layer::new("inner", trace),
+        ], || Err(Error::not_found("Not found.")));
+        assert_eq!(trace, ["outer.before", "inner.before", "api", "inner.error", "outer.error"]);
+        assert_eq!(status, StatusCode::NotFound);
+    }
+    #[test]
+    fn error_hooks_recover_and_fail() {
+        // The inner layer recovers from the error, and the middle one fails
+        // the recovered response again.
+        let (trace, status) = route(&|trace| {
+            let mut middle = Layer::new("middle", trace);
+            middle.fail_after = true;
+            let mut inner = Layer::new("inner", trace);
+            inner.recover = true;
+            vec![Layer::new("outer", trace), middle, inner]
+        }, || Err(Error::not_found("Not found.")));
+        assert_eq!(trace, [
+            "outer.before", "middle.before", "inner.before", "api",
+            "inner.error", "middle.after", "outer.error",
+        ]);
+        assert_eq!(status, StatusCode::InternalServerError);
+    }
+    #[test]
+    fn errors_before_routing_skip_before() {
+        let trace = Trace::default();
+        let mut outer = Layer::new("outer", &trace);
+        // It would accept the request if `before()` were called.
+        outer.short_circuit = Some(true);
+        let layers = middlewares(vec![outer, Layer::new("inner", &trace)]);
+        let mut req = Request::new(Method::Get);
+        let err = Error::payload_too_large("Too large.");
+        let res = run_error(&layers, &mut req, |_| Err(err));
+        assert_eq!(*trace.lock().unwrap(), ["inner.error", "outer.error"]);
+        assert_eq!(res.unwrap_err().status(), StatusCode::PayloadTooLarge);
+    }
+}
@@ -1,4 +1,11 @@
 use std::sync::Arc;
+use route_tree::RouteTree;
+use pattern::match_pattern;
+use middleware;
+use api::RouteFuture;
+use websocket::{Upgrade, WebSocketApi};
+use futures::{future, Future};
+use render::ErrorRenderer;
 use prelude::*;
 
 /// `Namespace` bind with apis and forms an intermediate layer of API. It self
@@ -10,25 +17,201 @@ use prelude::*;
 /// and name it `&[]`. But such design is not recommended because it sometimes
 /// will make the API work in a weird way, especially when path variables are
 /// involved, i.e. the trailing part of the path is used as a variable.
+///
+/// Names of sub-APIs are compiled into a prefix tree when they are binded, so
+/// the name of an API should never change after binding.
+///
+/// Middlewares can be attached to a namespace, so that they take effect on all
+/// the requests routed through the namespace. See `Middleware` for more
+/// information.
+///
+/// A maximum size of request body can be set for a namespace. It overrides the
+/// limit of lower hierarchy, and can be overridden by sub-namespaces.
+///
+/// A trailing slash policy can be set for a namespace, so that requests routed
+/// through the namespace are redirected to the canonical form of path. See
+/// `TrailingSlash` for more information.
+///
+/// Strict mode of deserialization can be set for a namespace, so that unknown
+/// fields in the inputs of requests routed through the namespace are rejected.
+/// It can be overridden by sub-namespaces. See `Request` for more information.
+///
+/// An error renderer can be set for a namespace, so that errors routed through
+/// the namespace are rendered by it, unless they are from a sub-namespace with
+/// its own renderer. See module `render` for more information.
 #[derive(Clone)]
 pub struct Namespace {
     name: &'static [&'static str],
-    apis: Vec<Arc<Api>>,
+    apis: Vec<Arc<dyn Api>>,
+    tree: RouteTree,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    body_limit: Option<usize>,
+    trailing_slash: Option<TrailingSlash>,
+    strict: Option<bool>,
+    renderer: Option<Arc<dyn ErrorRenderer>>,
 }
 impl Namespace {
     pub fn new(name: &'static [&'static str]) -> Namespace {
         Namespace {
-            name: name,
+            name,
             apis: Vec::new(),
+            tree: RouteTree::default(),
+            middlewares: Vec::new(),
+            body_limit: None,
+            trailing_slash: None,
+            strict: None,
+            renderer: None,
         }
     }
 
     pub fn with_api<A: Api>(mut self, api: A) -> Namespace {
-        self.apis.push(Arc::new(api) as Arc<Api>);
+        self.bind(api);
         self
     }
     pub fn bind<A: Api>(&mut self, api: A) {
-        self.apis.push(Arc::new(api) as Arc<Api>)
+        self.tree.insert(api.name(), self.apis.len());
+        self.apis.push(Arc::new(api) as Arc<dyn Api>)
+    }
+    pub fn with_async_api<A: AsyncApi>(self, api: A) -> Namespace {
+        self.with_api(Async(api))
+    }
+    /// Bind an asynchronous API. See `AsyncApi` for more information.
+    pub fn bind_async<A: AsyncApi>(&mut self, api: A) {
+        self.bind(Async(api))
+    }
+    pub fn with_websocket<A: WebSocketApi>(self, api: A) -> Namespace {
+        self.with_api(Upgrade(api))
+    }
+    /// Bind an API accepting WebSocket upgrades. See `WebSocketApi` for more
+    /// information.
+    pub fn bind_websocket<A: WebSocketApi>(&mut self, api: A) {
+        self.bind(Upgrade(api))
+    }
+
+    pub fn with_middleware<M: Middleware>(mut self, middleware: M) -> Namespace {
+        self.attach(middleware);
+        self
+    }
+    /// Attach a middleware. Middlewares are composed in attaching order, i.e.,
+    /// the first attached is the outermost.
+    pub fn attach<M: Middleware>(&mut self, middleware: M) {
+        self.middlewares.push(Arc::new(middleware) as Arc<dyn Middleware>)
+    }
+
+    pub fn with_body_limit(mut self, limit: usize) -> Namespace {
+        self.set_body_limit(limit);
+        self
+    }
+    /// Set the maximum size of request body in bytes. Requests with larger
+    /// bodies are rejected with `413 Payload Too Large`.
+    pub fn set_body_limit(&mut self, limit: usize) {
+        self.body_limit = Some(limit);
+    }
+
+    pub fn with_trailing_slash(mut self, policy: TrailingSlash) -> Namespace {
+        self.set_trailing_slash(policy);
+        self
+    }
+    /// Set the trailing slash policy. It overrides the policy of lower
+    /// hierarchy, and can be overridden by sub-namespaces.
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = Some(policy);
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Namespace {
+        self.set_strict(strict);
+        self
+    }
+    /// Set whether unknown fields are rejected in deserialization of requests
+    /// routed through this namespace.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = Some(strict);
+    }
+
+    pub fn with_renderer<R: ErrorRenderer>(mut self, renderer: R) -> Namespace {
+        self.set_renderer(renderer);
+        self
+    }
+    /// Set the renderer of errors routed through this namespace.
+    pub fn set_renderer<R: ErrorRenderer>(&mut self, renderer: R) {
+        self.renderer = Some(Arc::new(renderer) as Arc<dyn ErrorRenderer>);
+    }
+
+    /// Let the renderer of this namespace render the error, if any.
+    fn mark_renderer(&self, res: ApiResult) -> ApiResult {
+        match (res, &self.renderer) {
+            (Err(mut err), Some(renderer)) => {
+                err.set_default_renderer(renderer);
+                Err(err)
+            },
+            (res, _) => res,
+        }
+    }
+
+    /// Find the sub-API the path segments would be routed to, and the path
+    /// segments remaining for it.
+    fn resolve<'a>(&self, path_segs: &'a [String])
+        -> Option<(&Arc<dyn Api>, &'a [String])> {
+        let api = &self.apis[self.tree.find(path_segs)?];
+        let (len, _) = match_pattern(api.name(), path_segs)?;
+        Some((api, &path_segs[len..]))
+    }
+    /// Make a redirection to the canonical path, if the request path doesn't
+    /// follow the trailing slash policy in effect.
+    fn redirect_canonical(&self, req: &Request) -> Option<Response> {
+        // Leading slashes are collapsed, or `//host` would be a redirection to
+        // another host. Browsers take backslashes as slashes as well.
+        let path = req.raw_path().trim_start_matches(['/', '\\']);
+        let canonical = match self.trailing_slash(req.path_segs())? {
+            TrailingSlash::Always if !path.is_empty() && !path.ends_with('/') => {
+                format!("/{}/", path)
+            },
+            TrailingSlash::Never if path.ends_with('/') => {
+                format!("/{}", path.trim_end_matches('/'))
+            },
+            _ => return None,
+        };
+        let location = if req.query.is_empty() {
+            canonical
+        } else {
+            format!("{}?{}", canonical, req.query)
+        };
+        // Methods other than `GET` and `HEAD` shouldn't be changed.
+        let res = match req.method() {
+            Method::Get | Method::Head => Response::moved_permanently(&location),
+            _ => Response::permanent_redirect(&location),
+        };
+        Some(res)
+    }
+    fn dispatch(&self, req: &mut Request) -> ApiResult {
+        if let Some(strict) = self.strict {
+            req.set_strict(strict);
+        }
+        if let Some(res) = self.redirect_canonical(req) {
+            return Ok(res)
+        }
+        if let Some(idx) = self.tree.find(req.path_segs()) {
+            let api = &self.apis[idx];
+            if req.match_segs(api.name()) {
+                return api.route(req)
+            }
+        }
+        gen_api_not_found()
+    }
+    fn dispatch_async(&self, mut req: Request) -> RouteFuture {
+        if let Some(strict) = self.strict {
+            req.set_strict(strict);
+        }
+        if let Some(res) = self.redirect_canonical(&req) {
+            return Box::new(future::ok((req, Ok(res))))
+        }
+        if let Some(idx) = self.tree.find(req.path_segs()) {
+            let api = &self.apis[idx];
+            if req.match_segs(api.name()) {
+                return api.route_async(req)
+            }
+        }
+        Box::new(future::ok((req, gen_api_not_found())))
     }
 }
 impl Api for Namespace {
@@ -40,13 +223,88 @@ impl Api for Namespace {
     /// sub-API responded, the response is returned and the following it won't
     /// check the remaining unchecked sub-apis.
     fn route(&self, req: &mut Request) -> ApiResult {
-        for api in self.apis.iter() {
-            if req.match_segs(api.name()) {
-                return api.route(req)
-            }
+        let res = middleware::run(&self.middlewares, req, |req| self.dispatch(req));
+        self.mark_renderer(res)
+    }
+    fn route_async(&self, req: Request) -> RouteFuture {
+        let f_res = middleware::run_async(&self.middlewares, req, |req| self.dispatch_async(req));
+        match self.renderer {
+            Some(ref renderer) => {
+                let renderer = renderer.clone();
+                let f_res = f_res.map(move |(req, res)| {
+                    let res = res.map_err(|mut err| {
+                        err.set_default_renderer(&renderer);
+                        err
+                    });
+                    (req, res)
+                });
+                Box::new(f_res)
+            },
+            None => f_res,
         }
-        gen_api_not_found()
     }
+    fn body_limit(&self, path_segs: &[String]) -> Option<usize> {
+        self.resolve(path_segs)
+            .and_then(|(api, path_segs)| api.body_limit(path_segs))
+            .or(self.body_limit)
+    }
+    fn stream_body(&self, path_segs: &[String]) -> bool {
+        self.resolve(path_segs)
+            .is_some_and(|(api, path_segs)| api.stream_body(path_segs))
+    }
+    fn is_async(&self, path_segs: &[String]) -> bool {
+        self.resolve(path_segs)
+            .is_some_and(|(api, path_segs)| api.is_async(path_segs))
+    }
+    /// Requests not routed to any API are never redirected, so that they fail
+    /// with `404 Not Found` directly.
+    fn trailing_slash(&self, path_segs: &[String]) -> Option<TrailingSlash> {
+        match self.resolve(path_segs) {
+            Some((api, path_segs)) => api.trailing_slash(path_segs).or(self.trailing_slash),
+            None => Some(TrailingSlash::Ignore),
+        }
+    }
+    /// The error is passed to the sub-API the request would be routed to, then
+    /// through the middlewares of this namespace, whose `before()` is not
+    /// called.
+    fn route_error(&self, req: &mut Request, err: Error) -> ApiResult {
+        let res = middleware::run_error(&self.middlewares, req, |req| {
+            if let Some(idx) = self.tree.find(req.path_segs()) {
+                let api = &self.apis[idx];
+                if req.match_segs(api.name()) {
+                    return api.route_error(req, err)
+                }
+            }
+            Err(err)
+        });
+        self.mark_renderer(res)
+    }
+    fn info(&self) -> RouteInfo {
+        let children = self.apis.iter()
+            .map(|api| api.info())
+            .collect();
+        RouteInfo::new(self.name, RouteKind::Namespace)
+            .with_children(children)
+    }
+}
+
+/// Whether the paths routed through a namespace should end with slash. Requests
+/// not following the policy are redirected permanently, with `301 Moved
+/// Permanently` for `GET` and `HEAD`, or `308 Permanent Redirect` for the
+/// others.
+///
+/// Only requests to APIs are redirected; requests to paths under an API, e.g.,
+/// files served by `StaticFiles`, and those not routed to any API are not. The
+/// policy of the innermost namespace in the route is in effect, so that
+/// namespaces with different policies never redirect back and forth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Both forms are accepted. It's the default.
+    Ignore,
+    /// Paths should end with slash, e.g., `/docs/`.
+    Always,
+    /// Paths should not end with slash, e.g., `/docs`.
+    Never,
 }
 
 fn gen_api_not_found() -> ApiResult {
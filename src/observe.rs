@@ -0,0 +1,151 @@
+//! Observation of errors.
+//!
+//! Every error responded by `Writium` is reported to an `ErrorObserver`
+//! before it's rendered, along with the request it occurred in. Observers can
+//! be used to log errors in another way, or to report them to error
+//! collectors. `Log` is the default, which logs errors with `warn!`.
+use context::ErrorContext;
+use prelude::*;
+
+/// `ErrorObserver` is notified of every error responded. The causes of error
+/// can be retrieved by `Error::causes()`; the method, path, request ID and
+/// latency of the request are given in `ErrorContext`.
+///
+/// Observers are called on the thread routing the request, so they should
+/// return quickly.
+///
+/// Closures of signature `Fn(&Error, &ErrorContext)` are observers as well.
+pub trait ErrorObserver: 'static + Send + Sync {
+    fn observe(&self, err: &Error, ctx: &ErrorContext);
+}
+impl<F> ErrorObserver for F
+    where F: 'static + Send + Sync + Fn(&Error, &ErrorContext) {
+    fn observe(&self, err: &Error, ctx: &ErrorContext) {
+        self(err, ctx)
+    }
+}
+
+/// Log errors with `warn!`. Causes are logged as well for server errors.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Log;
+impl ErrorObserver for Log {
+    fn observe(&self, err: &Error, ctx: &ErrorContext) {
+        if err.status().is_server_error() {
+            let mut log = vec![format!("Unexpected error occured in {} {} ({}): {}",
+                ctx.method(), ctx.path(), ctx.request_id(), err)];
+            for cause in err.causes() {
+                log.push(format!("\tBy: {}", cause));
+            }
+            warn!("{}", log.join("\n"));
+        } else if err.status().is_client_error() {
+            warn!("Bad request induced an error in {} {} ({}): {}",
+                ctx.method(), ctx.path(), ctx.request_id(), err);
+        }
+    }
+}
+
+/// Ignore errors, so that nothing is logged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ignore;
+impl ErrorObserver for Ignore {
+    fn observe(&self, _err: &Error, _ctx: &ErrorContext) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, Once};
+    use log::{self, LogLevelFilter, LogMetadata, LogRecord};
+    use context::ErrorContext;
+    use error::Message;
+    use prelude::*;
+    use testing::TestClient;
+    use Writium;
+    use super::{ErrorObserver, Ignore, Log};
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Keep the records logged, as tests share the logger.
+    struct Capture;
+    impl log::Log for Capture {
+        fn enabled(&self, _metadata: &LogMetadata) -> bool {
+            true
+        }
+        fn log(&self, record: &LogRecord) {
+            RECORDS.lock().unwrap().push(format!("{}: {}", record.level(), record.args()));
+        }
+    }
+    /// Records of the path, which is unique to each test.
+    fn records(path: &str) -> Vec<String> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(|max_level| {
+                max_level.set(LogLevelFilter::Warn);
+                Box::new(Capture)
+            }).unwrap();
+        });
+        RECORDS.lock().unwrap().iter()
+            .filter(|x| x.contains(path))
+            .cloned()
+            .collect()
+    }
+    fn ctx(path: &str) -> ErrorContext {
+        records(path);
+        ErrorContext::new(Method::Post, path).with_request_id("42")
+    }
+
+    #[test]
+    fn server_errors_are_logged_with_causes() {
+        let err = Error::internal("Unable to save.")
+            .with_cause(Message("disk is full".to_owned()));
+        Log.observe(&err, &ctx("/log/server"));
+        assert_eq!(records("/log/server"), [
+            "WARN: Unexpected error occured in POST /log/server (42): Unable to save.\n\
+            \tBy: disk is full",
+        ]);
+    }
+    #[test]
+    fn client_errors_are_logged() {
+        let err = Error::not_found("Unable to find the book.")
+            .with_cause(Message("no such row".to_owned()));
+        Log.observe(&err, &ctx("/log/client"));
+        assert_eq!(records("/log/client"), [
+            "WARN: Bad request induced an error in POST /log/client (42): Unable to find the book.",
+        ]);
+    }
+    #[test]
+    fn ignored_errors_are_not_logged() {
+        Ignore.observe(&Error::internal("Unable to save."), &ctx("/log/ignore"));
+        assert!(records("/log/ignore").is_empty());
+    }
+
+    struct Fail;
+    impl Api for Fail {
+        fn name(&self) -> &[&str] {
+            &["fail"]
+        }
+        fn route(&self, req: &mut Request) -> ApiResult {
+            Err(Error::internal(format!("Failed {}.", req.request_id())))
+        }
+    }
+    #[test]
+    fn observers_see_request_ids() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut writium = Writium::new();
+        let seen_by_observer = seen.clone();
+        writium.set_observer(move |err: &Error, ctx: &ErrorContext| {
+            let record = (err.message().to_owned(), ctx.request_id().to_owned());
+            seen_by_observer.lock().unwrap().push(record);
+        });
+        writium.bind(Fail);
+        let client = TestClient::new(writium);
+        client.get("/fail").with_raw_header("X-Request-Id", "abc").send()
+            .assert_raw_header("X-Request-Id", "abc");
+        let res = client.get("/fail").send();
+        let generated = String::from_utf8(res.headers().get_raw("X-Request-Id")
+            .and_then(|x| x.one()).unwrap().to_vec()).unwrap();
+        assert_eq!(*seen.lock().unwrap(), [
+            ("Failed abc.".to_owned(), "abc".to_owned()),
+            (format!("Failed {}.", generated), generated.clone()),
+        ]);
+    }
+}
@@ -0,0 +1,451 @@
+//! OpenAPI 3 document generation.
+//!
+//! APIs can provide metadata with `Api::doc()`. `OpenApi` walks the route tree
+//! (see module `introspect`) and emits an OpenAPI 3 document describing all the
+//! binded APIs. Path patterns are translated into templated paths, e.g.,
+//! `["books", ":isbn"]` becomes `/books/{isbn}`, and path parameters are
+//! declared automatically if they are not documented.
+//!
+//! OpenAPI has no notion of globs, so a glob is emitted as a path parameter
+//! too, e.g., `["files", "*path"]` becomes `/files/{path}`. Note that it
+//! matches any number of path segments, including none, though it's not
+//! allowed in OpenAPI. The parameter declared automatically states that in its
+//! description.
+//!
+//! Templated paths equivalent to earlier-binded ones, e.g., `/books/{name}`
+//! after `/books/{isbn}`, are shadowed and thus left out.
+use std::collections::HashSet;
+use std::sync::Arc;
+use serde_json::{Map, Value};
+use introspect::ROOT_EXTRA_KEY;
+use pattern::Segment;
+use prelude::*;
+
+/// Metadata of an API.
+#[derive(Clone, Debug, Default)]
+pub struct ApiDoc {
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    /// Documented operations. Operations are also generated for the methods
+    /// the API declared but not documented.
+    pub operations: Vec<Operation>,
+}
+impl ApiDoc {
+    pub fn new() -> ApiDoc {
+        ApiDoc::default()
+    }
+    pub fn with_summary(mut self, summary: &str) -> ApiDoc {
+        self.summary = Some(summary.to_owned());
+        self
+    }
+    pub fn with_description(mut self, description: &str) -> ApiDoc {
+        self.description = Some(description.to_owned());
+        self
+    }
+    pub fn with_operation(mut self, op: Operation) -> ApiDoc {
+        self.operations.push(op);
+        self
+    }
+}
+
+fn json_content(schema: &Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// Metadata of an operation, i.e., the behavior of an API on a method.
+#[derive(Clone, Debug)]
+pub struct Operation {
+    pub method: Method,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub params: Vec<Param>,
+    /// JSON schema of request body.
+    pub request: Option<Value>,
+    pub responses: Vec<ResponseDoc>,
+}
+impl Operation {
+    pub fn new(method: Method) -> Operation {
+        Operation {
+            method,
+            summary: None,
+            description: None,
+            params: Vec::new(),
+            request: None,
+            responses: Vec::new(),
+        }
+    }
+    pub fn with_summary(mut self, summary: &str) -> Operation {
+        self.summary = Some(summary.to_owned());
+        self
+    }
+    pub fn with_description(mut self, description: &str) -> Operation {
+        self.description = Some(description.to_owned());
+        self
+    }
+    pub fn with_param(mut self, param: Param) -> Operation {
+        self.params.push(param);
+        self
+    }
+    /// Set the JSON schema of request body.
+    pub fn with_request(mut self, schema: Value) -> Operation {
+        self.request = Some(schema);
+        self
+    }
+    pub fn with_response(mut self, res: ResponseDoc) -> Operation {
+        self.responses.push(res);
+        self
+    }
+
+    fn to_json(&self, path_params: &[Param]) -> Value {
+        let mut rv = Map::new();
+        if let Some(ref summary) = self.summary {
+            rv.insert("summary".to_owned(), json!(summary));
+        }
+        if let Some(ref description) = self.description {
+            rv.insert("description".to_owned(), json!(description));
+        }
+        // Path parameters not documented are declared automatically.
+        let mut params: Vec<Value> = path_params.iter()
+            .filter(|x| !self.params.iter().any(|y| y.name == x.name &&
+                y.location == ParamLocation::Path))
+            .map(Param::to_json)
+            .collect();
+        params.extend(self.params.iter().map(Param::to_json));
+        if !params.is_empty() {
+            rv.insert("parameters".to_owned(), Value::Array(params));
+        }
+        if let Some(ref schema) = self.request {
+            rv.insert("requestBody".to_owned(), json!({
+                "required": true,
+                "content": json_content(schema),
+            }));
+        }
+        let mut responses = Map::new();
+        for res in self.responses.iter() {
+            let mut doc = json!({ "description": res.description });
+            if let Some(ref schema) = res.schema {
+                doc["content"] = json_content(schema);
+            }
+            responses.insert(res.status.as_u16().to_string(), doc);
+        }
+        if responses.is_empty() {
+            responses.insert("default".to_owned(), json!({
+                "description": "Unspecified response.",
+            }));
+        }
+        rv.insert("responses".to_owned(), Value::Object(responses));
+        Value::Object(rv)
+    }
+}
+
+/// Where a parameter is located.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamLocation {
+    Path,
+    Query,
+    Header,
+}
+impl ParamLocation {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ParamLocation::Path => "path",
+            ParamLocation::Query => "query",
+            ParamLocation::Header => "header",
+        }
+    }
+}
+
+/// Metadata of a parameter.
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub location: ParamLocation,
+    pub required: bool,
+    pub description: Option<String>,
+    /// JSON schema of the parameter.
+    pub schema: Value,
+}
+impl Param {
+    /// Create a parameter of type string. Path parameters are always required.
+    pub fn new(name: &str, location: ParamLocation) -> Param {
+        Param {
+            name: name.to_owned(),
+            location,
+            required: location == ParamLocation::Path,
+            description: None,
+            schema: json!({ "type": "string" }),
+        }
+    }
+    pub fn with_required(mut self, required: bool) -> Param {
+        self.required = required;
+        self
+    }
+    pub fn with_description(mut self, description: &str) -> Param {
+        self.description = Some(description.to_owned());
+        self
+    }
+    pub fn with_schema(mut self, schema: Value) -> Param {
+        self.schema = schema;
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut rv = json!({
+            "name": self.name,
+            "in": self.location.as_str(),
+            "required": self.required,
+            "schema": self.schema,
+        });
+        if let Some(ref description) = self.description {
+            rv["description"] = json!(description);
+        }
+        rv
+    }
+}
+
+/// Metadata of a response.
+#[derive(Clone, Debug)]
+pub struct ResponseDoc {
+    pub status: StatusCode,
+    pub description: String,
+    /// JSON schema of response body.
+    pub schema: Option<Value>,
+}
+impl ResponseDoc {
+    pub fn new(status: StatusCode, description: &str) -> ResponseDoc {
+        ResponseDoc {
+            status,
+            description: description.to_owned(),
+            schema: None,
+        }
+    }
+    pub fn with_schema(mut self, schema: Value) -> ResponseDoc {
+        self.schema = Some(schema);
+        self
+    }
+}
+
+/// `OpenApi` generates OpenAPI 3 documents. It's also an end-point API
+/// responding with the document of the whole application it's binded to, so
+/// the document is always in sync with the binded APIs.
+///
+/// As an API, it only works when requests are routed by `Writium`.
+pub struct OpenApi {
+    name: &'static [&'static str],
+    title: String,
+    version: String,
+}
+impl OpenApi {
+    pub fn new(name: &'static [&'static str], title: &str, version: &str) -> OpenApi {
+        OpenApi {
+            name,
+            title: title.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    /// Generate the document of the given route tree.
+    pub fn document(&self, info: &RouteInfo) -> Value {
+        let mut paths = Map::new();
+        collect_paths(info, &mut Vec::new(), &mut HashSet::new(), &mut paths);
+        json!({
+            "openapi": "3.0.0",
+            "info": {
+                "title": self.title,
+                "version": self.version,
+            },
+            "paths": paths,
+        })
+    }
+}
+impl Api for OpenApi {
+    fn name(&self) -> &[&str] {
+        self.name
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let info = req.extra::<Arc<Namespace>>(ROOT_EXTRA_KEY)
+            .ok_or_else(|| Error::internal("Root namespace is not available."))?
+            .info();
+        Response::new()
+            .with_json(&self.document(&info))
+    }
+    fn doc(&self) -> Option<ApiDoc> {
+        let op = Operation::new(Method::Get)
+            .with_summary("OpenAPI document of this application.")
+            .with_response(ResponseDoc::new(StatusCode::Ok, "OpenAPI document."));
+        Some(ApiDoc::new().with_operation(op))
+    }
+}
+
+/// Collect the documents of paths in `info`. `templates` records the paths
+/// collected with parameter names omitted, e.g., `/books/{}`, so that
+/// equivalent paths are detected.
+fn collect_paths(info: &RouteInfo, prefix: &mut Vec<String>,
+    templates: &mut HashSet<String>, paths: &mut Map<String, Value>) {
+    let depth = prefix.len();
+    prefix.extend(info.name.iter().cloned());
+    if info.kind == RouteKind::Namespace {
+        for child in info.children.iter() {
+            collect_paths(child, prefix, templates, paths);
+        }
+    } else {
+        let mut path = String::new();
+        let mut template = String::new();
+        let mut path_params = Vec::new();
+        for seg in prefix.iter() {
+            path.push('/');
+            template.push('/');
+            match Segment::parse(seg) {
+                Segment::Literal(lit) => {
+                    path.push_str(lit);
+                    template.push_str(lit);
+                },
+                Segment::Param(name) => {
+                    path.push_str(&format!("{{{}}}", name));
+                    template.push_str("{}");
+                    path_params.push(Param::new(name, ParamLocation::Path));
+                },
+                Segment::Glob(name) => {
+                    path.push_str(&format!("{{{}}}", name));
+                    template.push_str("{}");
+                    let param = Param::new(name, ParamLocation::Path)
+                        .with_description("All the remaining path segments. \
+                            It can be empty or contain `/`.");
+                    path_params.push(param);
+                },
+            }
+        }
+        if path.is_empty() {
+            path.push('/');
+            template.push('/');
+        }
+        // Earlier-binded APIs shadow later-binded ones on equivalent paths.
+        if templates.insert(template) {
+            paths.insert(path, path_item(info, &path_params));
+        }
+    }
+    prefix.truncate(depth);
+}
+
+fn path_item(info: &RouteInfo, path_params: &[Param]) -> Value {
+    let mut rv = Map::new();
+    let mut ops = Vec::new();
+    if let Some(ref doc) = info.doc {
+        if let Some(ref summary) = doc.summary {
+            rv.insert("summary".to_owned(), json!(summary));
+        }
+        if let Some(ref description) = doc.description {
+            rv.insert("description".to_owned(), json!(description));
+        }
+        ops.extend(doc.operations.iter().cloned());
+    }
+    // Methods declared but not documented are listed as well. `OPTIONS` and
+    // `HEAD` are usually answered automatically, so they are not worth listing.
+    let undocumented: Vec<Operation> = info.methods.iter()
+        .filter(|x| **x != Method::Options && **x != Method::Head)
+        .filter(|x| !ops.iter().any(|op| op.method == **x))
+        .map(|x| Operation::new(x.clone()))
+        .collect();
+    ops.extend(undocumented);
+    for op in ops.iter() {
+        let method = op.method.to_string().to_lowercase();
+        rv.insert(method, op.to_json(path_params));
+    }
+    if ops.is_empty() && !path_params.is_empty() {
+        let params = path_params.iter()
+            .map(Param::to_json)
+            .collect();
+        rv.insert("parameters".to_owned(), Value::Array(params));
+    }
+    Value::Object(rv)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use prelude::*;
+    use super::{OpenApi, Operation, Param, ParamLocation, ResponseDoc};
+
+    struct Files;
+    impl Api for Files {
+        fn name(&self) -> &[&str] {
+            &["files", "*path"]
+        }
+        fn route(&self, _req: &mut Request) -> ApiResult {
+            Ok(Response::new())
+        }
+    }
+
+    fn document(ns: Namespace) -> Value {
+        OpenApi::new(&["openapi.json"], "Test", "1.0")
+            .document(&ns.info())
+    }
+
+    #[test]
+    fn operations_are_documented() {
+        let op = Operation::new(Method::Get)
+            .with_summary("Get a book.")
+            .with_param(Param::new("fields", ParamLocation::Query))
+            .with_response(ResponseDoc::new(StatusCode::Ok, "The book.")
+                .with_schema(json!({ "type": "object" })));
+        let books = MethodRouter::new(&["books", ":isbn"])
+            .with_operation(op)
+            .with_get(|_| Ok(Response::new()))
+            .with_delete(|_| Ok(Response::new()));
+        let doc = document(Namespace::new(&[])
+            .with_api(Namespace::new(&["api"]).with_api(books)));
+        assert_eq!(doc["openapi"], "3.0.0");
+        assert_eq!(doc["info"], json!({ "title": "Test", "version": "1.0" }));
+        let item = &doc["paths"]["/api/books/{isbn}"];
+        let isbn = json!({
+            "name": "isbn",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        });
+        assert_eq!(item["get"], json!({
+            "summary": "Get a book.",
+            "parameters": [isbn, {
+                "name": "fields",
+                "in": "query",
+                "required": false,
+                "schema": { "type": "string" },
+            }],
+            "responses": {
+                "200": {
+                    "description": "The book.",
+                    "content": {
+                        "application/json": { "schema": { "type": "object" } },
+                    },
+                },
+            },
+        }));
+        // Undocumented methods are listed, but not `HEAD` and `OPTIONS`.
+        assert_eq!(item["delete"], json!({
+            "parameters": [isbn],
+            "responses": { "default": { "description": "Unspecified response." } },
+        }));
+        assert_eq!(item.as_object().unwrap().len(), 2);
+    }
+    #[test]
+    fn globs_are_noted() {
+        let doc = document(Namespace::new(&[]).with_api(Files));
+        let param = &doc["paths"]["/files/{path}"]["parameters"][0];
+        assert_eq!(param["name"], "path");
+        assert_eq!(param["description"],
+            "All the remaining path segments. It can be empty or contain `/`.");
+    }
+    #[test]
+    fn equivalent_paths_are_shadowed() {
+        let doc = document(Namespace::new(&[])
+            .with_api(MethodRouter::new(&["books", ":isbn"])
+                .with_get(|_| Ok(Response::new())))
+            .with_api(MethodRouter::new(&["books", ":name"])
+                .with_get(|_| Ok(Response::new())))
+            .with_api(MethodRouter::new(&["books", "new"])
+                .with_get(|_| Ok(Response::new()))));
+        let paths = doc["paths"].as_object().unwrap();
+        let paths: Vec<&str> = paths.keys().map(|x| x.as_str()).collect();
+        assert_eq!(paths, vec!["/books/new", "/books/{isbn}"]);
+    }
+}
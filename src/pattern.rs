@@ -0,0 +1,150 @@
+//! Path patterns used in API names.
+//!
+//! Each segment of an API name is interpreted as one of the following:
+//!
+//! * `:name` - a parameter matching exactly one path segment, which is
+//!   captured under `name`;
+//! * `*name` - a glob matching all the remaining path segments (possibly
+//!   none), which are captured, joined by `/`, under `name`. A glob should
+//!   always be the last segment of a name;
+//! * `\lit` - a literal `lit`, so that literals starting with `:`, `*` or `\`
+//!   can be matched, e.g., `\:id` (`"\\:id"` in Rust) matches the path
+//!   segment `:id`;
+//! * anything else - a literal which has to be equal to the path segment.
+//!
+//! Captured values are percent-decoded, as path segments are. They can be
+//! retrieved later with `Request::param()` and `Request::param_as()`. Note that
+//! a glob capture can't tell encoded slashes inside segments from those
+//! separating segments, e.g., both `/files/a/b` and `/files/a%2Fb` capture
+//! `a/b` by `["files", "*rest"]`; `Request::param_raw()` gives the value as is
+//! in the URI in such case.
+use std::ops::Range;
+
+/// A segment of API name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// Matches a path segment equal to the string.
+    Literal(&'a str),
+    /// Matches any single path segment and captures it by the given name.
+    Param(&'a str),
+    /// Matches all the remaining path segments and captures them by the given
+    /// name.
+    Glob(&'a str),
+}
+impl<'a> Segment<'a> {
+    /// Interpret a segment of API name.
+    pub fn parse(seg: &'a str) -> Segment<'a> {
+        if let Some(lit) = seg.strip_prefix('\\') {
+            Segment::Literal(lit)
+        } else if let Some(name) = seg.strip_prefix(':') {
+            Segment::Param(name)
+        } else if let Some(name) = seg.strip_prefix('*') {
+            Segment::Glob(name)
+        } else {
+            Segment::Literal(seg)
+        }
+    }
+}
+
+/// Match path segments against a pattern. The number of segments consumed and
+/// the captured values are returned if it's matching.
+pub fn match_pattern<S: AsRef<str>>(pattern: &[&str], path_segs: &[S])
+    -> Option<(usize, Vec<(String, String)>)> {
+    let (len, spans) = match_spans(pattern, path_segs)?;
+    let captures = spans.into_iter()
+        .map(|(name, span)| (name.to_owned(), join_segs(&path_segs[span])))
+        .collect();
+    Some((len, captures))
+}
+/// Name of a capture and the range of path segments it spans.
+pub type Span<'a> = (&'a str, Range<usize>);
+
+/// Match path segments against a pattern. The number of segments consumed and
+/// the spans of captures are returned if it's matching.
+pub fn match_spans<'a, S: AsRef<str>>(pattern: &[&'a str], path_segs: &[S])
+    -> Option<(usize, Vec<Span<'a>>)> {
+    let mut spans = Vec::new();
+    for (i, seg) in pattern.iter().enumerate() {
+        match Segment::parse(seg) {
+            Segment::Glob(name) => {
+                spans.push((name, i..path_segs.len()));
+                return Some((path_segs.len(), spans))
+            },
+            Segment::Param(name) => {
+                path_segs.get(i)?;
+                spans.push((name, i..i + 1));
+            },
+            Segment::Literal(lit) => {
+                if path_segs.get(i)?.as_ref() != lit {
+                    return None
+                }
+            },
+        }
+    }
+    Some((pattern.len(), spans))
+}
+
+/// Join path segments by `/`.
+pub(crate) fn join_segs<S: AsRef<str>>(segs: &[S]) -> String {
+    segs.iter()
+        .map(|x| x.as_ref())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_pattern, match_spans, Segment};
+
+    fn captures(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_segments() {
+        assert_eq!(Segment::parse("books"), Segment::Literal("books"));
+        assert_eq!(Segment::parse(":isbn"), Segment::Param("isbn"));
+        assert_eq!(Segment::parse("*rest"), Segment::Glob("rest"));
+        assert_eq!(Segment::parse(""), Segment::Literal(""));
+    }
+    #[test]
+    fn literals_match_exactly() {
+        assert_eq!(match_pattern(&["books", "new"], &["books", "new", "x"]),
+            Some((2, captures(&[]))));
+        assert_eq!(match_pattern(&["books", "new"], &["books", "old"]), None);
+        assert_eq!(match_pattern(&["books", "new"], &["books"]), None);
+        assert_eq!(match_pattern(&[], &["books"]), Some((0, captures(&[]))));
+    }
+    #[test]
+    fn params_capture_one_segment() {
+        assert_eq!(match_pattern(&["books", ":isbn", "chapters", ":n"],
+            &["books", "0451", "chapters", "3", "x"]),
+            Some((4, captures(&[("isbn", "0451"), ("n", "3")]))));
+        assert_eq!(match_pattern(&["books", ":isbn"], &["books"]), None);
+    }
+    #[test]
+    fn globs_capture_remaining_segments() {
+        assert_eq!(match_pattern(&["files", "*rest"], &["files", "a", "b.txt"]),
+            Some((3, captures(&[("rest", "a/b.txt")]))));
+        assert_eq!(match_pattern(&["files", "*rest"], &["files"]),
+            Some((1, captures(&[("rest", "")]))));
+        assert_eq!(match_pattern(&["files", "*rest"], &["docs", "a"]), None);
+    }
+    #[test]
+    fn spans_of_captures() {
+        assert_eq!(match_spans(&[":dir", "x", "*rest"], &["a", "x", "b", "c"]),
+            Some((4, vec![("dir", 0..1), ("rest", 2..4)])));
+        assert_eq!(match_spans(&["x", "*rest"], &["x"]),
+            Some((1, vec![("rest", 1..1)])));
+    }
+    #[test]
+    fn escaped_literals() {
+        assert_eq!(Segment::parse("\\:id"), Segment::Literal(":id"));
+        assert_eq!(Segment::parse("\\*"), Segment::Literal("*"));
+        assert_eq!(Segment::parse("\\\\"), Segment::Literal("\\"));
+        assert_eq!(match_pattern(&["\\:id"], &[":id"]), Some((1, captures(&[]))));
+        assert_eq!(match_pattern(&["\\:id"], &["1"]), None);
+    }
+}
@@ -0,0 +1,128 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use futures::Future;
+use futures_cpupool::{Builder, CpuPool};
+use proto::HyperResponse;
+
+/// What to do when a `Pool` is overloaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overload {
+    /// Respond with `503 Service Unavailable`. If a delay is given, it's told
+    /// to clients by `Retry-After` header.
+    Reject(Option<Duration>),
+    /// Route the request on the current thread, as if there is no pool.
+    Inline,
+}
+
+/// Worker thread pool where `Writium` routes buffered requests, so that slow
+/// synchronous APIs don't stall the event loop.
+///
+/// A pool has a fixed number of worker threads, and a queue of requests
+/// waiting for workers. When all the workers are busy and the queue is full,
+/// the pool is overloaded and incoming requests are handled according to the
+/// overload policy, rejected by default.
+///
+/// A pool of no worker thread is always overloaded, so that all the requests
+/// are handled according to the overload policy.
+pub struct Pool {
+    pool: Option<CpuPool>,
+    capacity: usize,
+    pending: Arc<AtomicUsize>,
+    overload: Overload,
+}
+impl Pool {
+    /// Create a pool of `size` worker threads, queueing at most `queue_len`
+    /// requests.
+    pub fn new(size: usize, queue_len: usize) -> Pool {
+        let pool = if size == 0 {
+            None
+        } else {
+            let pool = Builder::new()
+                .pool_size(size)
+                .name_prefix("writium-worker-")
+                .create();
+            Some(pool)
+        };
+        Pool {
+            pool,
+            capacity: if size == 0 { 0 } else { size + queue_len },
+            pending: Arc::new(AtomicUsize::new(0)),
+            overload: Overload::Reject(None),
+        }
+    }
+    /// Set the overload policy.
+    pub fn with_overload(mut self, overload: Overload) -> Pool {
+        self.overload = overload;
+        self
+    }
+    pub fn overload(&self) -> Overload {
+        self.overload
+    }
+    /// Number of requests being routed or waiting in queue.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Reserve a place for a job. `None` is returned if the pool is
+    /// overloaded.
+    pub(crate) fn reserve(&self) -> Option<Reservation> {
+        let reservation = Reservation(self.pending.clone());
+        if self.pending.fetch_add(1, Ordering::SeqCst) >= self.capacity {
+            // Dropping the reservation gives the place back.
+            None
+        } else {
+            Some(reservation)
+        }
+    }
+    /// Run the job on a worker.
+    pub(crate) fn spawn<F>(&self, reservation: Reservation, job: F)
+        -> Box<dyn Future<Item=HyperResponse, Error=Infallible>>
+        where F: 'static + FnOnce() -> HyperResponse + Send {
+        let pool = self.pool.as_ref()
+            .expect("reserved a place in a pool of no worker");
+        // The job is considered finished when it's dropped, even if it has
+        // panicked or has been cancelled.
+        let f_res = pool.spawn_fn(move || {
+            let _reservation = reservation;
+            Ok(job())
+        });
+        Box::new(f_res)
+    }
+}
+
+/// A place in the pool, given back on drop.
+pub(crate) struct Reservation(Arc<AtomicUsize>);
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pool;
+
+    #[test]
+    fn places_are_reserved() {
+        let pool = Pool::new(2, 1);
+        let a = pool.reserve().unwrap();
+        let b = pool.reserve().unwrap();
+        let c = pool.reserve().unwrap();
+        assert_eq!(pool.pending(), 3);
+        assert!(pool.reserve().is_none());
+        assert_eq!(pool.pending(), 3);
+        drop(b);
+        assert_eq!(pool.pending(), 2);
+        let d = pool.reserve().unwrap();
+        drop((a, c, d));
+        assert_eq!(pool.pending(), 0);
+    }
+    #[test]
+    fn pool_of_no_worker_is_overloaded() {
+        let pool = Pool::new(0, 4);
+        assert!(pool.reserve().is_none());
+        assert_eq!(pool.pending(), 0);
+    }
+}
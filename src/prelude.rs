@@ -3,11 +3,17 @@
 //! generally need `Writium` only.
 
 // Api and namespace implementation use.
-pub use api::{Api, ApiResult};
+pub use api::{Api, ApiResult, ApiFuture, AsyncApi, Async};
 pub use namespace::Namespace;
+pub use method_router::MethodRouter;
+pub use introspect::{RouteInfo, RouteKind};
+pub use openapi::ApiDoc;
+pub use middleware::Middleware;
+pub use cors::Cors;
+pub use websocket::{Session, WebSocketApi};
 
 // Request and response.
-pub use proto::{header, Request, Response, Headers, Method, StatusCode, Uri};
+pub use proto::{header, Chunk, Request, Response, Headers, Method, StatusCode, Uri};
 
 // Error handling.
 pub use error::{Error, Result};
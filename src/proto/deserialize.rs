@@ -0,0 +1,195 @@
+//! Deserialization of client inputs. Failures are reported with the path of
+//! the failing field, e.g., `books[2].isbn`, and the reason.
+use std::error::Error as StdError;
+use serde::de::{Deserialize, DeserializeOwned, Deserializer};
+use serde_ignored::{self, Path};
+use serde_path_to_error;
+use serde_json;
+use serde_qs::{Config, Error as QsError, QsDeserializer};
+use error::{Error, Message, Result};
+use super::StatusCode;
+
+/// Why deserialization failed.
+enum Failure<E> {
+    /// The field is malformed or its value is invalid.
+    Invalid(String, E),
+    /// The field is unknown, in strict mode.
+    Unknown(String),
+}
+
+/// Deserialize with the failing field tracked. Unknown fields are rejected in
+/// strict mode.
+fn deserialize<'de, D, T>(de: D, strict: bool) -> ::std::result::Result<T, Failure<D::Error>>
+    where D: Deserializer<'de>, T: Deserialize<'de> {
+    let mut unknown = None;
+    let rv = {
+        let mut record = |path: Path| {
+            if unknown.is_none() {
+                unknown = Some(fmt_path(&path));
+            }
+        };
+        let de = serde_ignored::Deserializer::new(de, &mut record);
+        serde_path_to_error::deserialize(de)
+    };
+    match (rv, unknown) {
+        (Ok(_), Some(field)) if strict => Err(Failure::Unknown(field)),
+        (Ok(rv), _) => Ok(rv),
+        (Err(err), _) => {
+            let field = err.path().to_string();
+            Err(Failure::Invalid(field, err.into_inner()))
+        },
+    }
+}
+
+/// Deserialize JSON. Syntax errors are `400 Bad Request`, and invalid or
+/// unknown fields are `422 Unprocessable Entity`.
+pub(crate) fn from_json<T>(input: &[u8], strict: bool, description: &'static str)
+    -> Result<T>
+    where T: DeserializeOwned {
+    let mut de = serde_json::Deserializer::from_slice(input);
+    deserialize(&mut de, strict)
+        .and_then(|rv| {
+            // Trailing characters are not allowed.
+            de.end().map_err(|err| Failure::Invalid(".".to_owned(), err))?;
+            Ok(rv)
+        })
+        .map_err(|failure| match failure {
+            Failure::Invalid(field, err) => {
+                let status = if err.is_data() {
+                    StatusCode::UnprocessableEntity
+                } else {
+                    StatusCode::BadRequest
+                };
+                let reason = err.to_string();
+                gen_invalid(status, description, field, reason, err)
+            },
+            Failure::Unknown(field) => gen_unknown(description, field),
+        })
+}
+/// Deserialize query string or form data. Malformed inputs are `400 Bad
+/// Request`, and invalid or unknown fields are `422 Unprocessable Entity`.
+pub(crate) fn from_qs<'de, T>(input: &'de [u8], strict: bool, description: &'static str)
+    -> Result<T>
+    where T: Deserialize<'de> {
+    let de = QsDeserializer::with_config(&Config::default(), input)
+        .map_err(|err| {
+            let reason = qs_reason(&err);
+            gen_invalid(StatusCode::BadRequest, description, ".".to_owned(),
+                reason.clone(), Message(reason))
+        })?;
+    deserialize(de, strict)
+        .map_err(|failure| match failure {
+            Failure::Invalid(field, err) => {
+                let reason = qs_reason(&err);
+                gen_invalid(StatusCode::UnprocessableEntity, description, field,
+                    reason.clone(), Message(reason))
+            },
+            Failure::Unknown(field) => gen_unknown(description, field),
+        })
+}
+
+/// Format the path of an ignored field like `serde_path_to_error` does.
+fn fmt_path(path: &Path) -> String {
+    fn fmt_segs(path: &Path) -> String {
+        match *path {
+            Path::Root => String::new(),
+            Path::Seq { parent, index } => format!("{}[{}]", fmt_segs(parent), index),
+            Path::Map { parent, ref key } => {
+                let parent = fmt_segs(parent);
+                if parent.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", parent, key)
+                }
+            },
+            Path::Some { parent } |
+            Path::NewtypeStruct { parent } |
+            Path::NewtypeVariant { parent } => fmt_segs(parent),
+        }
+    }
+    let rv = fmt_segs(path);
+    if rv.is_empty() { ".".to_owned() } else { rv }
+}
+
+/// `serde_qs` displays custom errors, which most errors are, as `Custom` only,
+/// so the message is taken from the debug form, e.g., `Error(Custom("invalid
+/// digit found in string"), ..)`; the kind of errors is not reachable without
+/// depending on the `error-chain` used by `serde_qs`. Other errors are
+/// displayed as is.
+///
+/// `serde_qs` errors are not thread-safe, so they are kept as `Message`s if
+/// they are causes.
+pub(crate) fn qs_reason(err: &QsError) -> String {
+    const CUSTOM: &str = "Custom(\"";
+    let debug = format!("{:?}", err);
+    debug.find(CUSTOM)
+        .and_then(|pos| unescape_debug(&debug[pos + CUSTOM.len()..]))
+        .unwrap_or_else(|| err.to_string())
+}
+/// Unescape a string in debug form till the closing quote.
+fn unescape_debug(quoted: &str) -> Option<String> {
+    let mut rv = String::new();
+    let mut chars = quoted.chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(rv),
+            '\\' => match chars.next()? {
+                'n' => rv.push('\n'),
+                'r' => rv.push('\r'),
+                't' => rv.push('\t'),
+                '0' => rv.push('\0'),
+                'u' => {
+                    // Escaped as `\u{7f}`.
+                    let rest = chars.as_str().strip_prefix('{')?;
+                    let end = rest.find('}')?;
+                    let c = u32::from_str_radix(&rest[..end], 16).ok()
+                        .and_then(char::from_u32)?;
+                    rv.push(c);
+                    chars = rest[end + 1..].chars();
+                },
+                c => rv.push(c),
+            },
+            c => rv.push(c),
+        }
+    }
+}
+
+fn gen_invalid<E>(status: StatusCode, description: &'static str, field: String,
+    reason: String, err: E) -> Error
+    where E: 'static + StdError + Send + Sync {
+    Error::new(status, description)
+        .with_detail(json!({ "field": field, "reason": reason }))
+        .with_cause(err)
+}
+fn gen_unknown(description: &'static str, field: String) -> Error {
+    Error::unprocessable_entity(description)
+        .with_detail(json!({ "field": field, "reason": "unknown field" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::{from_qs, unescape_debug};
+
+    #[test]
+    fn qs_reason_is_message() {
+        let err = from_qs::<BTreeMap<String, u32>>(b"a=1&b=x", false, "Invalid query.")
+            .unwrap_err();
+        assert_eq!(err.status().as_u16(), 422);
+        assert_eq!(err.detail(), Some(&json!({
+            "field": "b",
+            "reason": "invalid digit found in string",
+        })));
+    }
+    #[test]
+    fn qs_reason_is_unescaped() {
+        let err = from_qs::<BTreeMap<String, u32>>(b"a[b]=1", false, "Invalid query.")
+            .unwrap_err();
+        assert_eq!(err.detail(), Some(&json!({
+            "field": "a",
+            "reason": "Expected: \"u32\", got a Map",
+        })));
+        assert_eq!(unescape_debug(r#"a\"b\\c\n\u{7f}" rest"#).unwrap(), "a\"b\\c\n\u{7f}");
+        assert_eq!(unescape_debug(r#"unclosed"#), None);
+    }
+}
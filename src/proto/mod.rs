@@ -1,7 +1,8 @@
+mod deserialize;
 mod request;
-mod response;
+pub(crate) mod response;
 
-pub use self::request::{HyperRequest, Request};
-pub use self::response::{HyperResponse, Response};
+pub use self::request::{BodyStream, HyperRequest, Request};
+pub use self::response::{HyperResponse, Response, ResponseStream};
 
-pub use hyper::{header, Headers, Method, StatusCode, Uri};
+pub use hyper::{header, Chunk, Headers, Method, StatusCode, Uri};
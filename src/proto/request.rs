@@ -1,10 +1,15 @@
 use std::any::Any;
 use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use futures::{Poll, Stream};
 use serde::Serialize;
 use serde::de::{Deserialize, DeserializeOwned};
 use hyper::header::Header;
-use super::{Headers, Method, Uri};
+use super::{Chunk, Headers, Method, Uri};
+use super::deserialize;
 use error::{Result, Error};
+use pattern::{join_segs, match_spans};
 
 pub use hyper::Request as HyperRequest;
 
@@ -18,6 +23,10 @@ pub use hyper::Request as HyperRequest;
 /// interactions. But the simplified representation of request allows us to
 /// write concise codes, before we can return traits without boxing.
 ///
+/// APIs can opt in to receive the body as a stream by `Api`'s `stream_body()`.
+/// In that case, the body is not buffered and `body()` is always empty; the
+/// stream should be taken by `take_body_stream()` instead.
+///
 /// ## Extra
 ///
 /// Writium Framework allows low hierarchy to provide data for high hierarchy
@@ -29,35 +38,84 @@ pub use hyper::Request as HyperRequest;
 /// When a piece of data is put into a request, it cannot be withdrawed. API
 /// implementations should stay sane using extras to prevent unnecessary use of
 /// resources.
+///
+/// Extras have to be `Send`, as requests can be routed on worker threads. See
+/// `Writium`'s `set_pool()`.
+///
+/// ## Parameters
+///
+/// Path segments captured by patterns in API names (e.g. `:isbn` in
+/// `["books", ":isbn"]`) are recorded as parameters. They can be retrieved by
+/// name in all the APIs in higher hierarchy. See module `pattern` for more
+/// information.
+///
+/// ## Deserialization
+///
+/// Query strings and bodies deserialized by `to_param()`, `to_json()` and
+/// `to_form()` are client inputs, so failures are client errors: malformed
+/// inputs get `400 Bad Request`, and fields of invalid values get `422
+/// Unprocessable Entity`. The path of the failing field and the reason are
+/// given as error detail, e.g., `{"field": "books[2].isbn", "reason": ...}`.
+///
+/// Unknown fields are ignored by default. In strict mode, they are rejected
+/// with `422 Unprocessable Entity` as well. Strict mode can be set for
+/// namespaces. See `Namespace`'s `set_strict()`.
 #[derive(Debug)]
 pub struct Request {
     pub(crate) method: Method,
     pub(crate) query: String,
+    pub(crate) raw_path: String,
     pub(crate) path_segs: Vec<String>,
+    pub(crate) raw_segs: Vec<String>,
+    pub(crate) path_pos: usize,
     pub(crate) headers: Headers,
     pub(crate) body: Vec<u8>,
-    pub(crate) extra: BTreeMap<String, Box<Any>>,
+    pub(crate) body_stream: Option<BodyStream>,
+    pub(crate) params: BTreeMap<String, String>,
+    pub(crate) raw_params: BTreeMap<String, String>,
+    pub(crate) extra: BTreeMap<String, Box<dyn Any + Send>>,
+    pub(crate) strict: bool,
+    pub(crate) request_id: String,
 }
 impl Request {
     pub fn new(method: Method) -> Request {
         Request {
-            method: method, 
+            method,
             query: String::new(),
+            raw_path: String::new(),
             path_segs: Vec::new(),
+            raw_segs: Vec::new(),
+            path_pos: 0,
             headers: Headers::new(),
             body: Vec::new(),
+            body_stream: None,
+            params: BTreeMap::new(),
+            raw_params: BTreeMap::new(),
             extra: BTreeMap::new(),
+            strict: false,
+            request_id: String::new(),
         }
     }
     /// Get the HTTP method of the current request.
     pub fn method(&self) -> Method {
         self.method.clone()
     }
+    /// Get the ID identifying the request, which is echoed in the response as
+    /// `X-Request-Id`, so that logs can be correlated with clients. See
+    /// `ErrorContext` for how it's given. It's empty if the request is not
+    /// routed by `Writium`.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+    /// Whether unknown fields are rejected in deserialization.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
     /// Take the query part of URI and deserialize it into a structure, or a
     /// generic container.
     pub fn to_param<'de, T: Deserialize<'de>>(&'de self) -> Result<T> {
-        ::serde_qs::from_str(&self.query)
-            .map_err(|e| Error::internal("Unable to deserialize URI query.").with_cause(e))
+        deserialize::from_qs(self.query.as_bytes(), self.strict,
+            "Unable to deserialize URI query.")
     }
 
     /// Get the status code.
@@ -73,63 +131,70 @@ impl Request {
     /// `body()` for more information.
     pub fn to_str(&self) -> Result<&str> {
         ::std::str::from_utf8(&self.body)
-            .map_err(|e| Error::internal("Unable to parse body as string.").with_cause(e))
+            .map_err(|e| Error::bad_request("Unable to parse body as string.").with_cause(e))
     }
     /// Deserialize the body into a structure, or a generic container, only if
     /// the `Content-Type` is of type `application/json`.
     pub fn to_json<T: 'static + DeserializeOwned>(&self) -> Result<T> {
         use hyper::header::ContentType;
-        if let Some(&ContentType(ref ty)) = self.headers.get::<ContentType>() {
+        if let Some(ContentType(ty)) = self.headers.get::<ContentType>() {
             if ty.type_() != "application" || ty.subtype() != "json" {
                 let err = Error::bad_request("Content should be of type `application/json`.");
                 return Err(err)
             }
         }
-        ::serde_json::from_slice::<T>(&self.body)
-            .map_err(|e| Error::internal("Unable to deserialize body as JSON.").with_cause(e))
+        deserialize::from_json(&self.body, self.strict,
+            "Unable to deserialize body as JSON.")
     }
     /// Deserialize the body into a structure, or a generic container, only if
     /// the `Content-Type` is of type `application/x-www-form-urlencoded`.
     pub fn to_form<'de, T: Deserialize<'de>>(&'de self) -> Result<T> {
         use hyper::header::ContentType;
-        if let Some(&ContentType(ref ty)) = self.headers.get::<ContentType>() {
+        if let Some(ContentType(ty)) = self.headers.get::<ContentType>() {
             if ty.type_() != "application" || ty.subtype() != "x-www-form-urlencoded" {
                 let err = Error::bad_request("Content should be of type `application/x-www-form-urlencoded`.");
                 return Err(err)
             }
         }
-        ::serde_qs::from_bytes(&self.body)
-            .map_err(|e| Error::internal("Unable to parse body as form data.").with_cause(e))
+        deserialize::from_qs(&self.body, self.strict,
+            "Unable to parse body as form data.")
     }
 
     /// Set path segments and query string using given URI.
+    ///
+    /// Path segments are percent-decoded after the path is splitted, so
+    /// encoded slashes (`%2F`) are kept inside segments. Segments not valid
+    /// UTF-8 after decoding are decoded lossily; use `try_set_uri()` to reject
+    /// them instead.
     pub fn set_uri(&mut self, uri: &Uri) {
-        fn collect_path_segs(path: &str) -> Vec<String> {
-            let mut raw_rv = Vec::new();
-            if path.len() != 0 && path.as_bytes()[0] == b'/' {
-                let chars = if cfg!(windows) {
-                    path[1..].replace("\\", "/")
-                } else {
-                    path[1..].to_owned()
-                };
-                let iter = chars.split('/')
-                    .map(|x| x.to_string());
-                raw_rv.extend(iter);
-            }
-            // Protection for path traversal attack.
-            let mut rv = Vec::new();
-            for seg in raw_rv {
-                if seg == ".." {
-                    rv.pop();
-                } else if seg == "." || seg == "" {
-                } else {
-                    rv.push(seg);
-                }
-            }
-            rv
-        }
-        self.path_segs = collect_path_segs(uri.path());
+        let (path_segs, raw_segs, _) = collect_path_segs(uri.path());
+        self.path_segs = path_segs;
+        self.raw_segs = raw_segs;
+        self.path_pos = 0;
+        self.raw_path = uri.path().to_owned();
+        self.query = uri.query().unwrap_or_default().to_owned();
+    }
+    /// Set path segments and query string using given URI, as `set_uri()`
+    /// does. If any path segment is not valid UTF-8 after decoding, a 400
+    /// error is returned and the request is left unchanged.
+    pub fn try_set_uri(&mut self, uri: &Uri) -> Result<()> {
+        let (path_segs, raw_segs, valid) = collect_path_segs(uri.path());
+        valid?;
+        self.path_segs = path_segs;
+        self.raw_segs = raw_segs;
+        self.path_pos = 0;
+        self.raw_path = uri.path().to_owned();
         self.query = uri.query().unwrap_or_default().to_owned();
+        Ok(())
+    }
+    /// Prepare the request to be routed again from the root with the method.
+    /// Matched segments and captured parameters are discarded; extras are
+    /// kept.
+    pub(crate) fn rewind(&mut self, method: Method) {
+        self.method = method;
+        self.path_pos = 0;
+        self.params.clear();
+        self.raw_params.clear();
     }
     /// Set query string.
     pub fn set_query(&mut self, query: &str) {
@@ -137,9 +202,14 @@ impl Request {
     }
     /// Set path segments.
     pub fn set_path_segs(&mut self, path_segs: &[&str]) {
-        self.path_segs = path_segs.into_iter()
+        use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+        self.path_segs = path_segs.iter()
             .map(|x| (*x).to_owned())
             .collect();
+        self.raw_segs = path_segs.iter()
+            .map(|x| utf8_percent_encode(x, PATH_SEGMENT_ENCODE_SET).to_string())
+            .collect();
+        self.path_pos = 0;
     }
     /// Set a specific header.
     pub fn set_header<H: Header>(&mut self, header: H) {
@@ -165,11 +235,33 @@ impl Request {
             .map_err(|err| Error::internal("Unable to serialize data into JSON.").with_cause(err))
     }
 
-    /// Set path segments and query string using given URI.
+    /// Set the stream of body.
+    pub fn set_body_stream(&mut self, stream: BodyStream) {
+        self.body_stream = Some(stream);
+    }
+    /// Take the stream of body. `None` is returned if the body is buffered or
+    /// the stream has been taken.
+    pub fn take_body_stream(&mut self) -> Option<BodyStream> {
+        self.body_stream.take()
+    }
+    /// Set whether unknown fields are rejected in deserialization.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Set path segments and query string using given URI. Useful for
+    /// builder pattern. See `set_uri()` for more information.
     pub fn with_uri(mut self, uri: &Uri) -> Self {
         self.set_uri(uri);
         self
     }
+    /// Set path segments and query string using given URI, rejecting invalid
+    /// segments. Useful for builder pattern. See `try_set_uri()` for more
+    /// information.
+    pub fn try_with_uri(mut self, uri: &Uri) -> Result<Self> {
+        self.try_set_uri(uri)
+            .map(|_| { self })
+    }
     /// Set query string.
     pub fn with_query(mut self, query: &str) -> Self {
         self.query = query.to_owned();
@@ -202,6 +294,17 @@ impl Request {
         self.set_json(json)
             .map(|_| { self })
     }
+    /// Set the stream of body. Useful for builder pattern.
+    pub fn with_body_stream(mut self, stream: BodyStream) -> Self {
+        self.body_stream = Some(stream);
+        self
+    }
+    /// Set whether unknown fields are rejected in deserialization. Useful for
+    /// builder pattern.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
     /// Extra data derived by lower path hierarchy of APIs.
     pub fn extra<T: 'static>(&self, key: &str) -> Option<&T> {
@@ -209,13 +312,49 @@ impl Request {
             .and_then(|boxed| boxed.downcast_ref())
     }
     /// Put extra data into the current request.
-    pub fn set_extra<T: 'static + Any + Sized>(&mut self, key: &str, val: T) {
+    pub fn set_extra<T: 'static + Any + Send>(&mut self, key: &str, val: T) {
         self.extra.insert(key.to_string(), Box::new(val));
     }
 
-    /// Get the reference to internal path segment record.
+    /// Get a path parameter captured by name. The value is percent-decoded.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name)
+            .map(|x| x.as_str())
+    }
+    /// Get a path parameter captured by name, as is in the URI, i.e., not
+    /// percent-decoded. Parameters set by `set_param()` have no raw value.
+    pub fn param_raw(&self, name: &str) -> Option<&str> {
+        self.raw_params.get(name)
+            .map(|x| x.as_str())
+    }
+    /// Get a path parameter captured by name and parse it into the given type.
+    /// If the value cannot be parsed, a 400 error is returned.
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Result<T> {
+        self.param(name)
+            .ok_or_else(|| Error::internal(format!("Path parameter `{}` is not captured.", name)))?
+            .parse()
+            .map_err(|_| Error::bad_request(format!("Unable to parse path parameter `{}`.", name))
+                .with_detail(json!({ "param": name })))
+    }
+    /// Get all the path parameters captured.
+    pub fn params(&self) -> &BTreeMap<String, String> {
+        &self.params
+    }
+    /// Set a path parameter.
+    pub fn set_param(&mut self, name: &str, val: &str) {
+        self.params.insert(name.to_owned(), val.to_owned());
+        self.raw_params.remove(name);
+    }
+
+    /// Get the path of URI as is, i.e., not percent-decoded and not splitted.
+    /// Matched segments are included.
+    pub fn raw_path(&self) -> &str {
+        &self.raw_path
+    }
+    /// Get the reference to internal path segment record. Matched segments
+    /// are not included.
     pub fn path_segs(&self) -> &[String] {
-        &self.path_segs[..]
+        &self.path_segs[self.path_pos..]
     }
 
     /// Match a segment of path. If the preceding segment in the current request
@@ -223,26 +362,27 @@ impl Request {
     /// internal record and the matched segment is then returned. Otherwise,
     /// `false` is returned.
     pub fn match_seg(&mut self, seg: &str) -> bool {
-        if self.path_segs.len() > 0 &&
-            &self.path_segs[0] == seg {
-            self.path_segs.remove(0);
+        if self.path_segs().first().is_some_and(|x| x == seg) {
+            self.path_pos += 1;
             true
         } else {
             false
         }
     }
     /// Match several segments of path. It matches when and only when all the
-    /// segments are matching. See `match_segs()`.
+    /// segments are matching. See `match_seg()`.
+    ///
+    /// The segments are interpreted as a pattern, so parameters and globs are
+    /// captured on match. See module `pattern` for more information.
     pub fn match_segs(&mut self, segs: &[&str]) -> bool {
-        // The incoming segments should be fewer than the segments the current
-        // request can provide.
-        if self.path_segs.len() < segs.len() {
-            return false
-        }
-        if self.path_segs.iter()
-            .zip(segs.iter())
-            .all(|(req_seg, api_seg)| req_seg == api_seg) {
-            self.path_segs.drain(..segs.len());
+        if let Some((len, spans)) = match_spans(segs, self.path_segs()) {
+            let pos = self.path_pos;
+            for (name, span) in spans {
+                let span = pos + span.start..pos + span.end;
+                self.params.insert(name.to_owned(), join_segs(&self.path_segs[span.clone()]));
+                self.raw_params.insert(name.to_owned(), join_segs(&self.raw_segs[span]));
+            }
+            self.path_pos += len;
             true
         } else {
             false
@@ -254,22 +394,135 @@ impl Request {
     /// returned.
     pub fn match_seg_if<F>(&mut self, pred: F) -> Option<String>
         where F: Fn(&str) -> bool {
-        if self.path_segs.len() > 0 && pred(&self.path_segs[0]) {
-            Some(self.path_segs.remove(0))
-        } else {
-            None
+        let seg = self.path_segs().first()
+            .filter(|x| pred(x))
+            .cloned();
+        if seg.is_some() {
+            self.path_pos += 1;
         }
+        seg
     }
     /// Match all the segments of path while the given condition is satisfied.
     /// See `match_seg()`.
     pub fn match_segs_while<F>(&mut self, pred: F) -> Vec<String>
         where F: Fn(&str) -> bool {
-        use std::iter::FromIterator;
-        if let Some(false_point) = self.path_segs.iter()
-            .position(|x| !pred(x)) {
-            Vec::from_iter(self.path_segs.drain(..false_point))
+        let len = self.path_segs().iter()
+            .position(|x| !pred(x))
+            .unwrap_or_else(|| self.path_segs().len());
+        let rv = self.path_segs()[..len].to_vec();
+        self.path_pos += len;
+        rv
+    }
+}
+
+/// Split the path into segments, both percent-decoded and raw. The path is
+/// normalized in case of path traversal attack.
+fn collect_path_segs(path: &str) -> (Vec<String>, Vec<String>, Result<()>) {
+    use percent_encoding::percent_decode;
+    let mut segs = Vec::new();
+    let mut raw_segs = Vec::new();
+    let mut valid = Ok(());
+    if let Some(path) = path.strip_prefix('/') {
+        let chars = if cfg!(windows) {
+            path.replace("\\", "/")
         } else {
-            Vec::from_iter(self.path_segs.drain(..))
+            path.to_owned()
+        };
+        for raw_seg in chars.split('/') {
+            let seg: Vec<u8> = percent_decode(raw_seg.as_bytes()).collect();
+            let seg = String::from_utf8(seg).unwrap_or_else(|e| {
+                let lossy = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                if valid.is_ok() {
+                    valid = Err(Error::bad_request("Path is not valid UTF-8.").with_cause(e));
+                }
+                lossy
+            });
+            // Protection for path traversal attack.
+            if seg == ".." {
+                segs.pop();
+                raw_segs.pop();
+            } else if seg != "." && !seg.is_empty() {
+                segs.push(seg);
+                raw_segs.push(raw_seg.to_owned());
+            }
         }
     }
+    (segs, raw_segs, valid)
+}
+
+/// Stream of request body chunks.
+pub struct BodyStream(Box<dyn Stream<Item=Chunk, Error=Error> + Send>);
+impl BodyStream {
+    pub fn new<S>(stream: S) -> BodyStream
+        where S: 'static + Stream<Item=Chunk, Error=Error> + Send {
+        BodyStream(Box::new(stream))
+    }
+}
+impl Stream for BodyStream {
+    type Item = Chunk;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        self.0.poll()
+    }
+}
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        f.write_str("BodyStream")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prelude::*;
+
+    fn uri(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn request_is_send() {
+        fn is_send<T: Send>() {}
+        is_send::<Request>();
+    }
+    #[test]
+    fn segments_are_decoded() {
+        let req = Request::new(Method::Get)
+            .with_uri(&uri("/caf%C3%A9/a%2Fb/./x/../c?q=1"));
+        assert_eq!(req.path_segs(), ["café", "a/b", "c"]);
+        assert_eq!(req.raw_path(), "/caf%C3%A9/a%2Fb/./x/../c");
+        assert_eq!(req.query, "q=1");
+    }
+    #[test]
+    fn invalid_segments_are_rejected() {
+        let mut req = Request::new(Method::Get)
+            .with_uri(&uri("/a"));
+        let err = req.try_set_uri(&uri("/b/%FF")).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BadRequest);
+        assert_eq!(req.path_segs(), ["a"]);
+        assert_eq!(req.raw_path(), "/a");
+        req.set_uri(&uri("/b/%FF"));
+        assert_eq!(req.path_segs(), ["b", "\u{FFFD}"]);
+    }
+    #[test]
+    fn params_are_decoded_with_raw_values() {
+        let mut req = Request::new(Method::Get)
+            .with_uri(&uri("/files/x%3Ay/a/b%2Fc"));
+        assert!(req.match_segs(&["files", ":dir", "*rest"]));
+        assert_eq!(req.param("dir"), Some("x:y"));
+        assert_eq!(req.param_raw("dir"), Some("x%3Ay"));
+        assert_eq!(req.param("rest"), Some("a/b/c"));
+        assert_eq!(req.param_raw("rest"), Some("a/b%2Fc"));
+        req.set_param("dir", "z");
+        assert_eq!(req.param("dir"), Some("z"));
+        assert_eq!(req.param_raw("dir"), None);
+    }
+    #[test]
+    fn params_of_set_segments() {
+        let mut req = Request::new(Method::Get)
+            .with_path_segs(&["x", "a/b", "50%"]);
+        assert!(req.match_seg("x"));
+        assert!(req.match_segs(&["*rest"]));
+        assert_eq!(req.param("rest"), Some("a/b/50%"));
+        assert_eq!(req.param_raw("rest"), Some("a%2Fb/50%25"));
+    }
 }
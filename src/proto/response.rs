@@ -1,8 +1,15 @@
+use std::fmt::{Debug, Formatter, Result as FormatResult};
+use std::io::{Error as IoError, Read};
+use std::sync::OnceLock;
+use futures::{stream, Async, AsyncSink, Future, Poll, Sink, Stream};
+use futures::sync::mpsc::Sender;
+use futures_cpupool::{Builder, CpuPool};
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
-use hyper::header::Header;
-use super::{Headers, StatusCode};
-use error::{Error, Result};
+use hyper::header::{ContentLength, Header, Location};
+use super::{Chunk, Headers, Request, StatusCode};
+use error::{Error, Message, Result};
+use super::deserialize::qs_reason;
 
 pub use hyper::Response as HyperResponse;
 
@@ -10,11 +17,16 @@ pub use hyper::Response as HyperResponse;
 ///
 /// `Response` is similar to `HyperResponse`, but it provides useful interfaces
 /// for convenience.
+///
+/// The body is buffered by default. To send large contents without
+/// materializing them, the body can be set to a stream of chunks instead, which
+/// is sent chunked. In that case `body()` is always empty.
 #[derive(Debug)]
 pub struct Response {
     status: StatusCode,
     headers: Headers,
     body: Vec<u8>,
+    body_stream: Option<ResponseStream>,
 }
 impl Response {
     /// Create a new `Response` instance.
@@ -23,16 +35,78 @@ impl Response {
             status: StatusCode::Ok,
             headers: Headers::new(),
             body: Vec::new(),
+            body_stream: None,
         }
     }
+    /// Create a `201 Created` response referring to the created resource.
+    pub fn created(location: &str) -> Self {
+        Response::new()
+            .with_status(StatusCode::Created)
+            .with_header(Location::new(location.to_owned()))
+    }
+    /// Create a `204 No Content` response.
+    pub fn no_content() -> Self {
+        Response::new()
+            .with_status(StatusCode::NoContent)
+    }
+    /// Create a redirection response of the given status code, which should
+    /// be of 3xx.
+    pub fn redirect(status: StatusCode, location: &str) -> Self {
+        Response::new()
+            .with_status(status)
+            .with_header(Location::new(location.to_owned()))
+    }
+    /// Create a `301 Moved Permanently` response. Clients might change the
+    /// method to `GET` when redirected.
+    pub fn moved_permanently(location: &str) -> Self {
+        Response::redirect(StatusCode::MovedPermanently, location)
+    }
+    /// Create a `302 Found` response. Clients might change the method to `GET`
+    /// when redirected.
+    pub fn found(location: &str) -> Self {
+        Response::redirect(StatusCode::Found, location)
+    }
+    /// Create a `303 See Other` response. Clients always redirect with `GET`.
+    pub fn see_other(location: &str) -> Self {
+        Response::redirect(StatusCode::SeeOther, location)
+    }
+    /// Create a `307 Temporary Redirect` response. Clients redirect with the
+    /// same method and body.
+    pub fn temporary_redirect(location: &str) -> Self {
+        Response::redirect(StatusCode::TemporaryRedirect, location)
+    }
+    /// Create a `308 Permanent Redirect` response. Clients redirect with the
+    /// same method and body.
+    pub fn permanent_redirect(location: &str) -> Self {
+        Response::redirect(StatusCode::PermanentRedirect, location)
+    }
+    /// Resolve the relative `Location` against the path of request URI, e.g.,
+    /// `../list` in response to `/books/42/edit` is resolved to `/books/list`.
+    /// Absolute locations are kept as is. Useful for builder pattern.
+    pub fn resolve_location(mut self, req: &Request) -> Self {
+        let location = self.headers.get::<Location>()
+            .map(|x| resolve_location(req.raw_path(), &x.to_string()));
+        if let Some(location) = location {
+            self.headers.set(Location::new(location));
+        }
+        self
+    }
     /// Get the status code.
     pub fn status(&self) -> StatusCode {
-        self.status.clone()
+        self.status
     }
-    /// Get the status code.
+    /// Get a specific header.
     pub fn header<H: Header>(&self) -> Option<&H> {
         self.headers.get::<H>()
     }
+    /// Get all the headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+    /// Get all the headers for modification.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
     /// Get the status code.
     pub fn body(&self) -> &[u8] {
         &self.body
@@ -48,7 +122,7 @@ impl Response {
     /// the `Content-Type` is of type `application/json`.
     pub fn to_json<T: 'static + DeserializeOwned>(&self) -> Result<T> {
         use hyper::header::ContentType;
-        if let Some(&ContentType(ref ty)) = self.headers.get::<ContentType>() {
+        if let Some(ContentType(ty)) = self.headers.get::<ContentType>() {
             if ty.type_() != "application" || ty.subtype() != "json" {
                 let err = Error::bad_request("Content should be of type `application/json`.");
                 return Err(err)
@@ -61,14 +135,17 @@ impl Response {
     /// the `Content-Type` is of type `application/x-www-form-urlencoded`.
     pub fn to_form<'de, T: Deserialize<'de>>(&'de self) -> Result<T> {
         use hyper::header::ContentType;
-        if let Some(&ContentType(ref ty)) = self.headers.get::<ContentType>() {
+        if let Some(ContentType(ty)) = self.headers.get::<ContentType>() {
             if ty.type_() != "application" || ty.subtype() != "x-www-form-urlencoded" {
                 let err = Error::bad_request("Content should be of type `application/x-www-form-urlencoded`.");
                 return Err(err)
             }
         }
         ::serde_qs::from_bytes(&self.body)
-            .map_err(|e| Error::internal("Unable to parse body as form data.").with_cause(e))
+            .map_err(|e| {
+                Error::internal("Unable to parse body as form data.")
+                    .with_cause(Message(qs_reason(&e)))
+            })
     }
 
     /// Set a status code.
@@ -87,6 +164,17 @@ impl Response {
     pub fn set_body<B>(&mut self, body: B) where B: Into<Vec<u8>> {
         self.body = body.into();
     }
+    /// Set response content as a stream of chunks. Buffered content is
+    /// discarded.
+    pub fn set_body_stream<S>(&mut self, stream: S)
+        where S: 'static + Stream<Item=Chunk, Error=Error> + Send {
+        self.body = Vec::new();
+        self.body_stream = Some(ResponseStream(Box::new(stream)));
+    }
+    /// Take the stream of response content, if any.
+    pub fn take_body_stream(&mut self) -> Option<ResponseStream> {
+        self.body_stream.take()
+    }
     /// Set response content serialized from json. The content type will be set
     /// to `application/json`.
     pub fn set_json<T: Serialize>(&mut self, json: &T) -> Result<()> {
@@ -126,14 +214,212 @@ impl Response {
         self.set_json(json)
             .map(|_| { self })
     }
+    /// Set response content as a stream of chunks. Useful for builder pattern.
+    pub fn with_body_stream<S>(mut self, stream: S) -> Self
+        where S: 'static + Stream<Item=Chunk, Error=Error> + Send {
+        self.set_body_stream(stream);
+        self
+    }
+    /// Set response content as chunks yielded by an iterator. Useful for
+    /// builder pattern.
+    pub fn with_body_iter<I>(self, iter: I) -> Self
+        where I: IntoIterator,
+              I::Item: 'static + Into<Chunk>,
+              I::IntoIter: 'static + Send {
+        let stream = stream::iter_ok(iter.into_iter().map(Into::into));
+        self.with_body_stream(stream)
+    }
+    /// Set response content as what is read from a reader. The reader is read
+    /// in blocks as the content is being sent. Each read blocks a thread of
+    /// the pool sending streams, so the reader should not block for long, as
+    /// files don't; see `ResponseStream`. Useful for builder pattern.
+    pub fn with_body_reader<R>(self, reader: R) -> Self
+        where R: 'static + Read + Send {
+        self.with_body_stream(stream::iter_result(ReadChunks(reader)))
+    }
+}
+
+/// Resolve a URI reference against the base path as is described in RFC 3986.
+fn resolve_location(base: &str, location: &str) -> String {
+    let end = location.find(['/', '?', '#']).unwrap_or(location.len());
+    // Absolute URI or network-path reference.
+    if location.starts_with("//") || location[..end].contains(':') {
+        return location.to_owned()
+    }
+    let (path, rest) = location.split_at(location.find(['?', '#'])
+        .unwrap_or(location.len()));
+    let base = if base.starts_with('/') { base } else { "/" };
+    let merged = if path.is_empty() {
+        base.to_owned()
+    } else if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        // There is at least one slash in base.
+        let dir = base.rfind('/').map_or(0, |x| x + 1);
+        format!("{}{}", &base[..dir], path)
+    };
+    // Remove dot segments.
+    let segs: Vec<&str> = merged.split('/').skip(1).collect();
+    let mut rv = Vec::new();
+    for (i, seg) in segs.iter().enumerate() {
+        let is_last = i + 1 == segs.len();
+        match *seg {
+            "." => {},
+            ".." => { rv.pop(); },
+            seg => {
+                rv.push(seg);
+                continue
+            },
+        }
+        // Dot segments at the end refer to directories.
+        if is_last {
+            rv.push("");
+        }
+    }
+    format!("/{}{}", rv.join("/"), rest)
+}
+
+/// Stream of response content chunks. It's sent on a pool of threads shared by
+/// all the streams, so it has to be `Send`. Polling the stream occupies a
+/// thread of the pool, so it should not block for long, or the other streams
+/// are delayed. The number of threads can be set by `Writium`'s
+/// `set_stream_threads()`.
+pub struct ResponseStream(Box<dyn Stream<Item=Chunk, Error=Error> + Send>);
+impl Stream for ResponseStream {
+    type Item = Chunk;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        self.0.poll()
+    }
+}
+impl Debug for ResponseStream {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        f.write_str("ResponseStream")
+    }
+}
+
+/// Iterator over blocks read from a reader.
+struct ReadChunks<R: Read>(R);
+impl<R: Read> Iterator for ReadChunks<R> {
+    type Item = Result<Chunk>;
+    fn next(&mut self) -> Option<Result<Chunk>> {
+        let mut buf = vec![0; 8192];
+        match self.0.read(&mut buf) {
+            Ok(0) => None,
+            Ok(len) => {
+                buf.truncate(len);
+                Some(Ok(buf.into()))
+            },
+            Err(err) => Some(Err(Error::internal("Unable to read response content.").with_cause(err))),
+        }
+    }
+}
+impl Default for Response {
+    fn default() -> Response {
+        Response::new()
+    }
+}
+impl Response {
+    /// Transform into `HyperResponse`, sending the stream of content, if any,
+    /// on the given pool.
+    pub(crate) fn into_hyper(self, stream_pool: &CpuPool) -> HyperResponse {
+        let Response { status, headers, body, body_stream } = self;
+        let res = HyperResponse::new()
+            .with_status(status)
+            .with_headers(headers);
+        // The version is left to the server, which follows the request.
+        match body_stream {
+            // Sent chunked unless the length is given.
+            Some(stream) => res.with_body(send_stream(stream, stream_pool)),
+            None => {
+                let mut res = res;
+                if allows_body(status) && !res.headers().has::<ContentLength>() {
+                    res.headers_mut().set(ContentLength(body.len() as u64));
+                }
+                res.with_body(body)
+            },
+        }
+    }
 }
+
 impl From<Response> for HyperResponse {
+    /// The stream of content, if any, is sent on a pool of as many threads as
+    /// CPUs, shared by the whole process.
     fn from(res: Response) -> HyperResponse {
-        let Response { status, headers, body } = res;
-        HyperResponse::new()
-            .with_status(status)
-            .with_headers(headers)
-            .with_body(body)
-            // TODO: Update according to the version in request later?
+        res.into_hyper(default_stream_pool())
+    }
+}
+
+/// Whether a response of the status can have a body.
+pub(crate) fn allows_body(status: StatusCode) -> bool {
+    !status.is_informational() &&
+        status != StatusCode::NoContent &&
+        status != StatusCode::NotModified
+}
+
+/// Send the chunks of stream through a hyper body. The stream is driven on the
+/// given pool; it's only polled when it can make progress, so idle streams and
+/// slow clients don't occupy threads.
+fn send_stream(stream: ResponseStream, pool: &CpuPool) -> ::hyper::Body {
+    let (tx, body) = ::hyper::Body::pair();
+    let feed = Feed {
+        stream,
+        tx,
+        pending: None,
+    };
+    pool.spawn(feed).forget();
+    body
+}
+/// Create a pool of threads sending streams. The number of threads defaults to
+/// the number of CPUs.
+pub(crate) fn new_stream_pool(threads: Option<usize>) -> CpuPool {
+    let mut builder = Builder::new();
+    if let Some(threads) = threads {
+        builder.pool_size(threads);
+    }
+    builder.name_prefix("writium-stream-")
+        .create()
+}
+fn default_stream_pool() -> &'static CpuPool {
+    static POOL: OnceLock<CpuPool> = OnceLock::new();
+    POOL.get_or_init(|| new_stream_pool(None))
+}
+
+/// Future sending the chunks of stream through the sender of a hyper body,
+/// until the stream ends, fails, or the receiver has gone.
+struct Feed {
+    stream: ResponseStream,
+    tx: Sender<::std::result::Result<Chunk, ::hyper::Error>>,
+    /// Chunk taken from the stream but not yet accepted by the sender.
+    pending: Option<::std::result::Result<Chunk, ::hyper::Error>>,
+}
+impl Future for Feed {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Some(chunk) = self.pending.take() {
+                let is_err = chunk.is_err();
+                match self.tx.start_send(chunk) {
+                    Ok(AsyncSink::Ready) if is_err => return Ok(Async::Ready(())),
+                    Ok(AsyncSink::Ready) => {},
+                    Ok(AsyncSink::NotReady(chunk)) => {
+                        self.pending = Some(chunk);
+                        return Ok(Async::NotReady)
+                    },
+                    // The receiver has gone.
+                    Err(_) => return Ok(Async::Ready(())),
+                }
+            }
+            self.pending = match self.stream.poll() {
+                Ok(Async::Ready(Some(chunk))) => Some(Ok(chunk)),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    warn!("Error occurred streaming response: {}", err);
+                    Some(Err(::hyper::Error::from(IoError::other(err.to_string()))))
+                },
+            };
+        }
     }
 }
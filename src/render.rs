@@ -0,0 +1,167 @@
+//! Rendering of errors into responses.
+//!
+//! `Writium` renders errors with an `ErrorRenderer`, which can be overridden
+//! for the errors raised in a namespace, e.g., HTML pages for browser routes
+//! and JSON for API routes. The innermost namespace having a renderer renders
+//! the error; `Writium`'s renderer is used if no namespace has one.
+//!
+//! `Negotiate` is the default, which chooses one of `Json`, `Html` and
+//! `PlainText` with the `Accept` header.
+use std::mem;
+use hyper::header::{q, ContentLength, ContentType, Quality, Vary};
+use hyper::mime::{self, Mime};
+use unicase::Ascii;
+use context::ErrorContext;
+use proto::HyperResponse;
+use prelude::*;
+
+/// `ErrorRenderer` makes the final response of errors. Headers of the error
+/// should be kept in the response.
+///
+/// Closures of signature `Fn(Error, &ErrorContext) -> HyperResponse` are
+/// renderers as well.
+pub trait ErrorRenderer: 'static + Send + Sync {
+    fn render(&self, err: Error, ctx: &ErrorContext) -> HyperResponse;
+}
+impl<F> ErrorRenderer for F
+    where F: 'static + Send + Sync + Fn(Error, &ErrorContext) -> HyperResponse {
+    fn render(&self, err: Error, ctx: &ErrorContext) -> HyperResponse {
+        self(err, ctx)
+    }
+}
+
+/// Render errors as JSON in the format configured on `Writium`. See `Error`
+/// for more information.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+impl ErrorRenderer for Json {
+    fn render(&self, err: Error, ctx: &ErrorContext) -> HyperResponse {
+        err.into_response(ctx.format())
+    }
+}
+
+/// Render errors as minimal HTML pages.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Html;
+impl ErrorRenderer for Html {
+    fn render(&self, err: Error, _ctx: &ErrorContext) -> HyperResponse {
+        let heading = escape_html(&format!("{} {}", err.status().as_u16(), title(&err)));
+        let body = format!("<!DOCTYPE html>\n<html>\n<head>\n\
+            <meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n\
+            <h1>{}</h1>\n<p>{}</p>\n</body>\n</html>\n",
+            heading, heading, escape_html(err.message()));
+        respond(err, mime::TEXT_HTML_UTF_8, body)
+    }
+}
+
+/// Render errors as plain text, which reads well in terminals.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainText;
+impl ErrorRenderer for PlainText {
+    fn render(&self, err: Error, _ctx: &ErrorContext) -> HyperResponse {
+        let body = format!("{} {}\n{}\n", err.status().as_u16(), title(&err),
+            err.message());
+        respond(err, mime::TEXT_PLAIN_UTF_8, body)
+    }
+}
+
+/// Render errors in the type most preferred by the client, among JSON, HTML
+/// and plain text. JSON is preferred if the client has no preference, or
+/// accepts none of them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Negotiate;
+impl ErrorRenderer for Negotiate {
+    fn render(&self, err: Error, ctx: &ErrorContext) -> HyperResponse {
+        let json = quality(ctx, &["application/json", "application/problem+json"]);
+        let html = quality(ctx, &["text/html"]);
+        let text = quality(ctx, &["text/plain"]);
+        let mut res = if html > json && html >= text {
+            Html.render(err, ctx)
+        } else if text > json && text > html {
+            PlainText.render(err, ctx)
+        } else {
+            Json.render(err, ctx)
+        };
+        // The response varies on `Accept`, in addition to what the error has.
+        let accept = Ascii::new("Accept".to_owned());
+        let vary = match res.headers().get::<Vary>() {
+            Some(Vary::Any) => None,
+            Some(Vary::Items(items)) if items.contains(&accept) => None,
+            Some(Vary::Items(items)) => {
+                let mut items = items.clone();
+                items.push(accept);
+                Some(Vary::Items(items))
+            },
+            None => Some(Vary::Items(vec![accept])),
+        };
+        if let Some(vary) = vary {
+            res.headers_mut().set(vary);
+        }
+        res
+    }
+}
+
+/// Quality of the most preferred one of the media types, by the most specific
+/// media range matching it. Everything is acceptable without `Accept`.
+fn quality(ctx: &ErrorContext, types: &[&str]) -> Quality {
+    let accept = match ctx.accept() {
+        Some(accept) => accept,
+        None => return Quality::default(),
+    };
+    types.iter()
+        .filter_map(|ty| ty.parse::<Mime>().ok())
+        .map(|ty| {
+            accept.iter()
+                .filter_map(|item| {
+                    let range = &item.item;
+                    let specificity = if range.type_() == mime::STAR {
+                        0
+                    } else if range.type_() != ty.type_() {
+                        return None
+                    } else if range.subtype() == mime::STAR {
+                        1
+                    } else if range.subtype() == ty.subtype() &&
+                        range.suffix() == ty.suffix() {
+                        2
+                    } else {
+                        return None
+                    };
+                    Some((specificity, item.quality))
+                })
+                .max_by_key(|&(specificity, _)| specificity)
+                .map(|(_, quality)| quality)
+                .unwrap_or_else(|| q(0))
+        })
+        .max()
+        .unwrap_or_else(|| q(0))
+}
+
+fn title(err: &Error) -> &str {
+    err.title()
+        .or_else(|| err.status().canonical_reason())
+        .unwrap_or_default()
+}
+fn escape_html(text: &str) -> String {
+    let mut rv = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => rv.push_str("&amp;"),
+            '<' => rv.push_str("&lt;"),
+            '>' => rv.push_str("&gt;"),
+            '"' => rv.push_str("&quot;"),
+            '\'' => rv.push_str("&#39;"),
+            _ => rv.push(c),
+        }
+    }
+    rv
+}
+fn respond(mut err: Error, content_type: Mime, body: String) -> HyperResponse {
+    let headers = mem::take(err.headers_mut());
+    let body = body.into_bytes();
+    HyperResponse::new()
+        .with_status(err.status())
+        .with_headers(headers)
+        .with_header(ContentType(content_type))
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
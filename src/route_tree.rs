@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use pattern::Segment;
+
+/// Prefix tree of API names compiled at binding time. Each API is identified
+/// by the order it's binded.
+///
+/// Lookup yields the earliest-binded API whose name matches a prefix of the
+/// path, which is exactly the API a linear scan in binding order would find.
+/// Subtrees only containing later-binded APIs than the best candidate found so
+/// far are not visited at all, so the cost of lookup is proportional to the
+/// depth of path in most cases.
+#[derive(Clone, Default)]
+pub struct RouteTree {
+    root: Node,
+}
+impl RouteTree {
+    /// Insert the name (pattern) of the `idx`-th binded API.
+    pub fn insert(&mut self, name: &[&str], idx: usize) {
+        let mut node = &mut self.root;
+        node.update_min(idx);
+        for seg in name {
+            node = match Segment::parse(seg) {
+                Segment::Literal(lit) => node.literals
+                    .entry(lit.to_owned())
+                    .or_default(),
+                Segment::Param(_) => node.param
+                    .get_or_insert_with(Default::default),
+                Segment::Glob(_) => {
+                    node.glob = Some(node.glob.map_or(idx, |x| x.min(idx)));
+                    return
+                },
+            };
+            node.update_min(idx);
+        }
+        node.end = Some(node.end.map_or(idx, |x| x.min(idx)));
+    }
+    /// Find the earliest-binded API matching the path segments.
+    pub fn find<S: AsRef<str>>(&self, path_segs: &[S]) -> Option<usize> {
+        let mut best = None;
+        self.root.find(path_segs, &mut best);
+        best
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    /// The smallest index of all the APIs in this subtree.
+    min: usize,
+    /// The smallest index of APIs whose name terminates at this node.
+    end: Option<usize>,
+    /// The smallest index of APIs whose name ends with a glob at this node.
+    glob: Option<usize>,
+    literals: HashMap<String, Node>,
+    param: Option<Box<Node>>,
+}
+impl Default for Node {
+    fn default() -> Node {
+        Node {
+            min: usize::MAX,
+            end: None,
+            glob: None,
+            literals: HashMap::new(),
+            param: None,
+        }
+    }
+}
+impl Node {
+    fn update_min(&mut self, idx: usize) {
+        self.min = self.min.min(idx);
+    }
+    fn find<S: AsRef<str>>(&self, path_segs: &[S], best: &mut Option<usize>) {
+        fn offer(best: &mut Option<usize>, idx: Option<usize>) {
+            if let Some(idx) = idx {
+                *best = Some(best.map_or(idx, |x| x.min(idx)));
+            }
+        }
+        if best.is_some_and(|x| x <= self.min) {
+            return
+        }
+        offer(best, self.end);
+        offer(best, self.glob);
+        if let Some((seg, rest)) = path_segs.split_first() {
+            if let Some(child) = self.literals.get(seg.as_ref()) {
+                child.find(rest, best);
+            }
+            if let Some(ref child) = self.param {
+                child.find(rest, best);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pattern::match_pattern;
+    use super::RouteTree;
+
+    /// Index of the first API in binding order matching the path.
+    fn linear_find(names: &[Vec<&str>], path: &[&str]) -> Option<usize> {
+        names.iter().position(|name| match_pattern(name, path).is_some())
+    }
+    fn build(names: &[Vec<&str>]) -> RouteTree {
+        let mut tree = RouteTree::default();
+        for (idx, name) in names.iter().enumerate() {
+            tree.insert(name, idx);
+        }
+        tree
+    }
+    fn assert_same(names: &[Vec<&str>], paths: &[Vec<&str>]) {
+        let tree = build(names);
+        for path in paths {
+            assert_eq!(tree.find(path), linear_find(names, path),
+                "names: {:?}, path: {:?}", names, path);
+        }
+    }
+
+    #[test]
+    fn literal_before_param() {
+        let names = vec![vec!["books", "new"], vec!["books", ":id"]];
+        let tree = build(&names);
+        assert_eq!(tree.find(&["books", "new"]), Some(0));
+        assert_eq!(tree.find(&["books", "42"]), Some(1));
+        assert_eq!(tree.find(&["books"]), None);
+    }
+    #[test]
+    fn param_before_literal() {
+        let names = vec![vec!["books", ":id"], vec!["books", "new"]];
+        let tree = build(&names);
+        assert_eq!(tree.find(&["books", "new"]), Some(0));
+        assert_eq!(tree.find(&["books", "42", "x"]), Some(0));
+    }
+    #[test]
+    fn glob_matches_remaining() {
+        let names = vec![vec!["files", "readme"], vec!["files", "*rest"]];
+        let tree = build(&names);
+        assert_eq!(tree.find(&["files", "readme"]), Some(0));
+        assert_eq!(tree.find(&["files"]), Some(1));
+        assert_eq!(tree.find(&["files", "a", "b"]), Some(1));
+    }
+    #[test]
+    fn fuse_shadows_later_apis() {
+        let names = vec![vec!["a"], vec![], vec!["b"]];
+        let tree = build(&names);
+        assert_eq!(tree.find(&["a", "x"]), Some(0));
+        assert_eq!(tree.find(&["b"]), Some(1));
+        assert_eq!(tree.find::<&str>(&[]), Some(1));
+    }
+    #[test]
+    fn same_as_linear_scan() {
+        const SEGS: &[&str] = &["a", "b", ":p", ":q", "*g"];
+        const PATH_SEGS: &[&str] = &["a", "b", "c"];
+        // Deterministic pseudo-random names, to cover many binding orders.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |n: usize| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((state >> 33) as usize) % n
+        };
+        let mut paths = vec![vec![]];
+        for len in 1..4 {
+            let mut longer = Vec::new();
+            for path in paths.iter().filter(|x: &&Vec<&str>| x.len() == len - 1) {
+                for seg in PATH_SEGS {
+                    let mut path = path.clone();
+                    path.push(*seg);
+                    longer.push(path);
+                }
+            }
+            paths.extend(longer);
+        }
+        for _ in 0..500 {
+            let names = (0..1 + next(6))
+                .map(|_| {
+                    let mut name = Vec::new();
+                    for _ in 0..next(4) {
+                        let seg = SEGS[next(SEGS.len())];
+                        name.push(seg);
+                        if seg.starts_with('*') {
+                            break
+                        }
+                    }
+                    name
+                })
+                .collect::<Vec<_>>();
+            assert_same(&names, &paths);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::str;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+use futures::{stream, Stream};
+use futures::sync::mpsc::{self, Sender};
+use hyper::header::{CacheControl, CacheDirective, ContentType, Formatter, Header,
+    Raw};
+use hyper::mime::TEXT_EVENT_STREAM;
+use prelude::*;
+
+/// Comment frame sent to keep connections alive.
+const KEEP_ALIVE_FRAME: &str = ":\n\n";
+
+/// An event of Server-Sent Events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<u64>,
+}
+impl Event {
+    /// Create an event carrying the data. Multi-line data is sent as multiple
+    /// `data:` fields; lines can end with `\r\n`, `\r` or `\n`, as in event
+    /// streams.
+    pub fn new<S: Into<String>>(data: S) -> Event {
+        Event {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+    /// Create an event carrying data serialized as json.
+    pub fn json<T: ::serde::Serialize>(data: &T) -> Result<Event> {
+        ::serde_json::to_string(data)
+            .map(Event::new)
+            .map_err(|err| Error::internal("Unable to serialize event data.")
+                .with_cause(err))
+    }
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+    pub fn event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+    /// Set the event ID, which clients send back in `Last-Event-ID` when they
+    /// reconnect.
+    pub fn with_id<S: Into<String>>(mut self, id: S) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+    /// Set the event type. Clients dispatch the event to listeners of this
+    /// type, instead of `message`.
+    pub fn with_event<S: Into<String>>(mut self, event: S) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+    /// Set the time in milliseconds clients wait before reconnecting.
+    pub fn with_retry(mut self, retry: u64) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+    /// Format the event as a frame in the event stream.
+    pub fn to_frame(&self) -> String {
+        let mut frame = String::new();
+        // Line breaks are not allowed in fields other than data. IDs having
+        // NUL are ignored by clients.
+        if let Some(ref event) = self.event {
+            let _ = writeln!(frame, "event: {}", event.replace(['\r', '\n'], ""));
+        }
+        if let Some(ref id) = self.id {
+            let _ = writeln!(frame, "id: {}", clean_id(id));
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(frame, "retry: {}", retry);
+        }
+        // Each line break makes a new field, or the rest of the line would be
+        // taken as another field.
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            let _ = writeln!(frame, "data: {}", line);
+        }
+        frame.push('\n');
+        frame
+    }
+}
+
+/// Make the event ID to be sent, as clients would send it back.
+fn clean_id(id: &str) -> String {
+    id.replace(['\r', '\n', '\0'], "")
+        .trim()
+        .to_owned()
+}
+
+/// `Last-Event-ID` header, sent by clients reconnecting to an event stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LastEventId(pub String);
+impl Header for LastEventId {
+    fn header_name() -> &'static str {
+        "Last-Event-ID"
+    }
+    fn parse_header(raw: &Raw) -> ::hyper::Result<LastEventId> {
+        raw.one()
+            .and_then(|x| str::from_utf8(x).ok())
+            .map(|x| LastEventId(x.trim().to_owned()))
+            .ok_or(::hyper::Error::Header)
+    }
+    fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+        f.fmt_line(&self.0)
+    }
+}
+
+/// Make a response sending the events as an event stream.
+///
+/// The response is streamed, so the connection is occupied until the stream
+/// ends. See `Response::with_body_stream()` for more information.
+pub fn response<S>(events: S) -> Response
+    where S: 'static + Stream<Item=Event, Error=Error> + Send {
+    let frames = events.map(|event| Chunk::from(event.to_frame()));
+    Response::new()
+        .with_header(ContentType(TEXT_EVENT_STREAM))
+        .with_header(CacheControl(vec![CacheDirective::NoCache]))
+        .with_body_stream(frames)
+}
+
+struct HubInner {
+    subscribers: Vec<Sender<Chunk>>,
+    history: VecDeque<Event>,
+    history_len: usize,
+    buffer: usize,
+    next_id: u64,
+}
+impl HubInner {
+    /// Send a frame to all the subscribers. Subscribers which have gone or
+    /// can't keep up are dropped; they can resume with `Last-Event-ID` when
+    /// they reconnect.
+    fn broadcast(&mut self, frame: &str) {
+        self.subscribers.retain_mut(|tx| {
+            tx.try_send(Chunk::from(frame.to_owned())).is_ok()
+        });
+    }
+}
+
+/// `Hub` is an in-process broadcast channel of Server-Sent Events. APIs publish
+/// events to a hub, and the events are fanned out to all the event streams
+/// subscribed to it.
+///
+/// Published events are assigned sequential IDs if they have none. The latest
+/// events are kept in history, so that clients reconnecting with
+/// `Last-Event-ID` receive the events they missed. If the event of the ID is
+/// no longer in history, e.g., the client has been away for long or the server
+/// has restarted, the whole history is sent, as the client might have missed
+/// all of it.
+///
+/// Subscriptions don't occupy threads while they are idle, but
+/// disconnections are only noticed when something is sent, so keep-alive
+/// should be enabled with `with_keep_alive()` for hubs which are rarely
+/// published to.
+///
+/// `Hub` is cheap to clone; clones share the same subscribers.
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Mutex<HubInner>>,
+}
+impl Hub {
+    pub fn new() -> Hub {
+        let inner = HubInner {
+            subscribers: Vec::new(),
+            history: VecDeque::new(),
+            history_len: 64,
+            buffer: 64,
+            next_id: 1,
+        };
+        Hub {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+    /// Set the number of latest events kept for resumption. 64 by default.
+    pub fn with_history(self, history_len: usize) -> Hub {
+        {
+            let mut inner = self.lock();
+            inner.history_len = history_len;
+            while inner.history.len() > history_len {
+                inner.history.pop_front();
+            }
+        }
+        self
+    }
+    /// Set the number of events buffered for each subscriber. Subscribers
+    /// lagging behind more than that are disconnected. 64 by default.
+    pub fn with_buffer(self, buffer: usize) -> Hub {
+        self.lock().buffer = buffer;
+        self
+    }
+    /// Send a comment to all the subscribers periodically, so that
+    /// intermediaries don't close idle connections, and disconnected
+    /// subscribers are dropped in time. A thread is spawned for it, which exits
+    /// when all the clones of the hub are dropped.
+    pub fn with_keep_alive(self, interval: Duration) -> Hub {
+        let inner = Arc::downgrade(&self.inner);
+        let spawned = thread::Builder::new()
+            .name("writium-sse-keep-alive".to_owned())
+            .spawn(move || keep_alive(&inner, interval));
+        if let Err(err) = spawned {
+            warn!("Unable to spawn thread for keep-alive: {}", err);
+        }
+        self
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, HubInner> {
+        // Nothing panics while the lock is held, but recover anyway.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Publish an event to all the subscribers. The ID of the event is
+    /// returned.
+    pub fn publish(&self, event: Event) -> String {
+        let mut inner = self.lock();
+        let event = match event.id {
+            Some(_) => event,
+            None => {
+                let id = inner.next_id.to_string();
+                inner.next_id += 1;
+                event.with_id(id)
+            },
+        };
+        inner.broadcast(&event.to_frame());
+        if inner.history_len > 0 {
+            if inner.history.len() == inner.history_len {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(event.clone());
+        }
+        event.id.unwrap_or_default()
+    }
+    /// Number of the subscribers currently connected, as far as the hub knows.
+    pub fn subscriber_count(&self) -> usize {
+        self.lock().subscribers.len()
+    }
+    /// Subscribe to the hub, making a response which streams the events
+    /// published from now on. If the request has `Last-Event-ID`, the events
+    /// after it in history are sent first; if the event is no longer in
+    /// history, the whole history is sent.
+    pub fn subscribe(&self, req: &Request) -> Response {
+        let last_id = req.header::<LastEventId>().map(|x| x.0.as_str());
+        let mut inner = self.lock();
+        let missed: Vec<Chunk> = match last_id {
+            Some(last_id) => {
+                let skip = inner.history.iter()
+                    .position(|x| x.id().map(clean_id).as_deref() == Some(last_id))
+                    .map_or(0, |x| x + 1);
+                inner.history.iter()
+                    .skip(skip)
+                    .map(|x| Chunk::from(x.to_frame()))
+                    .collect()
+            },
+            None => Vec::new(),
+        };
+        let (tx, rx) = mpsc::channel(inner.buffer);
+        inner.subscribers.push(tx);
+        let frames = stream::iter_ok(missed)
+            .chain(rx.map_err(|_| Error::internal("Event hub is broken.")));
+        Response::new()
+            .with_header(ContentType(TEXT_EVENT_STREAM))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+            .with_body_stream(frames)
+    }
+}
+impl Default for Hub {
+    fn default() -> Hub {
+        Hub::new()
+    }
+}
+
+fn keep_alive(inner: &Weak<Mutex<HubInner>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match inner.upgrade() {
+            Some(inner) => inner.lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .broadcast(KEEP_ALIVE_FRAME),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use prelude::*;
+    use super::{Event, Hub, LastEventId};
+
+    /// Take the frames sent by the response.
+    fn frames(mut res: Response, n: usize) -> Vec<String> {
+        res.take_body_stream().unwrap()
+            .take(n as u64)
+            .map(|x| String::from_utf8(x.to_vec()).unwrap())
+            .collect()
+            .wait()
+            .unwrap()
+    }
+    fn subscribe(hub: &Hub, last_id: Option<&str>) -> Response {
+        let mut req = Request::new(Method::Get);
+        if let Some(last_id) = last_id {
+            req.set_header(LastEventId(last_id.to_owned()));
+        }
+        hub.subscribe(&req)
+    }
+
+    #[test]
+    fn frame_has_all_fields() {
+        let event = Event::new("hello")
+            .with_id("7")
+            .with_event("greeting")
+            .with_retry(3000);
+        assert_eq!(event.to_frame(), "event: greeting\nid: 7\nretry: 3000\ndata: hello\n\n");
+        assert_eq!(Event::new("").to_frame(), "data: \n\n");
+    }
+    #[test]
+    fn data_lines_are_split_at_all_line_breaks() {
+        let event = Event::new("a\r\nb\rc\nd\n\re");
+        assert_eq!(event.to_frame(), "data: a\ndata: b\ndata: c\ndata: d\ndata: \ndata: e\n\n");
+        // No field can be injected.
+        let event = Event::new("hi\rid: 999\revent: admin");
+        assert_eq!(event.to_frame(), "data: hi\ndata: id: 999\ndata: event: admin\n\n");
+    }
+    #[test]
+    fn fields_have_no_line_breaks() {
+        let event = Event::new("x")
+            .with_id("1\r\ndata: y\0")
+            .with_event("a\rretry: 1\nb");
+        assert_eq!(event.to_frame(), "event: aretry: 1b\nid: 1data: y\ndata: x\n\n");
+    }
+
+    #[test]
+    fn events_are_broadcast() {
+        let hub = Hub::new();
+        let a = subscribe(&hub, None);
+        let b = subscribe(&hub, None);
+        assert_eq!(hub.subscriber_count(), 2);
+        assert_eq!(hub.publish(Event::new("x")), "1");
+        assert_eq!(hub.publish(Event::new("y").with_id("custom")), "custom");
+        let expected = vec!["id: 1\ndata: x\n\n", "id: custom\ndata: y\n\n"];
+        assert_eq!(frames(a, 2), expected);
+        assert_eq!(frames(b, 2), expected);
+    }
+    #[test]
+    fn missed_events_are_resent() {
+        let hub = Hub::new();
+        for data in &["a", "b", "c"] {
+            hub.publish(Event::new(*data));
+        }
+        let res = subscribe(&hub, Some("2"));
+        hub.publish(Event::new("d"));
+        assert_eq!(frames(res, 2), vec!["id: 3\ndata: c\n\n", "id: 4\ndata: d\n\n"]);
+        // Nothing is resent without `Last-Event-ID`.
+        let res = subscribe(&hub, None);
+        hub.publish(Event::new("e"));
+        assert_eq!(frames(res, 1), vec!["id: 5\ndata: e\n\n"]);
+    }
+    #[test]
+    fn whole_history_is_resent_for_unknown_id() {
+        let hub = Hub::new()
+            .with_history(2);
+        for data in &["a", "b", "c"] {
+            hub.publish(Event::new(*data));
+        }
+        // Event 1 has been dropped from history.
+        let res = subscribe(&hub, Some("1"));
+        assert_eq!(frames(res, 2), vec!["id: 2\ndata: b\n\n", "id: 3\ndata: c\n\n"]);
+    }
+    #[test]
+    fn lagging_subscribers_are_dropped() {
+        let hub = Hub::new()
+            .with_buffer(2);
+        let lagging = subscribe(&hub, None);
+        for i in 0..8 {
+            hub.publish(Event::new(i.to_string()));
+        }
+        assert_eq!(hub.subscriber_count(), 0);
+        // The events buffered are still sent, then the stream ends.
+        let sent = frames(lagging, 100);
+        assert!(!sent.is_empty() && sent.len() < 8, "{:?}", sent);
+        assert_eq!(sent[0], "id: 1\ndata: 0\n\n");
+        // It can resume from where it lagged.
+        let res = subscribe(&hub, Some(&sent.len().to_string()));
+        let resumed = frames(res, 8 - sent.len());
+        assert_eq!(resumed.last().unwrap(), "id: 8\ndata: 7\n\n");
+    }
+}
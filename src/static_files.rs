@@ -0,0 +1,225 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use hyper::header::{AcceptRanges, Allow, ContentLength,
+    ContentRange, ContentRangeSpec, ContentType, ETag, EntityTag, HttpDate,
+    IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range, RangeUnit};
+use mime_guess;
+use prelude::*;
+
+/// `StaticFiles` serves files in a directory. The remaining path segments are
+/// mapped onto the root directory, e.g., with name `["assets"]`, request to
+/// `/assets/css/main.css` is served with `<root>/css/main.css`.
+///
+/// Requests to directories are served with the first existing index file,
+/// `index.html` by default. Requests to directories without trailing slash are
+/// redirected to the path with trailing slash first, so that relative links in
+/// index files work. Only `GET` and `HEAD` are allowed.
+///
+/// Responses are cacheable with `ETag` and `Last-Modified`, and partial
+/// requests with a single byte range are supported. Multiple ranges are not,
+/// in which case the entire file is served.
+///
+/// Files are resolved with symlinks followed; files resolved outside the root
+/// directory are never served.
+pub struct StaticFiles {
+    name: &'static [&'static str],
+    root: PathBuf,
+    index: Vec<String>,
+}
+impl StaticFiles {
+    pub fn new<P: AsRef<Path>>(name: &'static [&'static str], root: P) -> StaticFiles {
+        StaticFiles {
+            name,
+            root: root.as_ref().to_owned(),
+            index: vec!["index.html".to_owned()],
+        }
+    }
+    /// Set the index files tried in order for requests to directories. Empty
+    /// list lets requests to directories fail with `404 Not Found`.
+    pub fn with_index(mut self, index: &[&str]) -> StaticFiles {
+        self.index = index.iter().map(|x| (*x).to_owned()).collect();
+        self
+    }
+
+    /// Find the file to serve for the path segments. Whether the file is the
+    /// index of a directory is returned as well.
+    fn resolve(&self, path_segs: &[String]) -> Result<(PathBuf, Metadata, bool)> {
+        let root = self.root.canonicalize()
+            .map_err(|err| Error::internal("Unable to access root directory.")
+                .with_cause(err))?;
+        let mut path = root.clone();
+        for seg in path_segs {
+            // Decoded segments might contain separators.
+            if seg.is_empty() || seg == "." || seg == ".." ||
+                seg.contains(['/', '\\', '\0']) {
+                return Err(gen_file_not_found())
+            }
+            path.push(seg);
+        }
+        let path = canonicalize(&path)?;
+        if !path.starts_with(&root) {
+            return Err(gen_file_not_found())
+        }
+        let meta = metadata(&path)?;
+        if !meta.is_dir() {
+            return Ok((path, meta, false))
+        }
+        for index in &self.index {
+            let path = match path.join(index).canonicalize() {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            if path.starts_with(&root) {
+                let meta = metadata(&path)?;
+                if meta.is_file() {
+                    return Ok((path, meta, true))
+                }
+            }
+        }
+        Err(gen_file_not_found())
+    }
+}
+impl Api for StaticFiles {
+    fn name(&self) -> &[&str] {
+        self.name
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let method = req.method();
+        if method != Method::Get && method != Method::Head {
+            return Err(Error::method_not_allowed()
+                .with_header(Allow(vec![Method::Get, Method::Head])))
+        }
+        let (path, meta, is_index) = self.resolve(req.path_segs())?;
+        if is_index && !req.raw_path().ends_with('/') {
+            return Ok(redirect_to_dir(req))
+        }
+        let len = meta.len();
+        let modified = meta.modified().ok();
+        let etag = gen_etag(len, modified);
+
+        let mut res = Response::new()
+            .with_header(ContentType(mime_guess::from_path(&path).first_or_octet_stream()))
+            .with_header(AcceptRanges(vec![RangeUnit::Bytes]))
+            .with_header(ETag(etag.clone()));
+        if let Some(modified) = modified {
+            res.set_header(LastModified(HttpDate::from(modified)));
+        }
+        if is_not_modified(req, &etag, modified) {
+            return Ok(res.with_status(StatusCode::NotModified))
+        }
+
+        let range = match req.header::<Range>() {
+            Some(Range::Bytes(specs)) if specs.len() == 1 &&
+                is_range_fresh(req, &etag, modified) => {
+                match specs[0].to_satisfiable_range(len) {
+                    Some(range) => Some(range),
+                    None => {
+                        let spec = ContentRangeSpec::Bytes {
+                            range: None,
+                            instance_length: Some(len),
+                        };
+                        let err = Error::new(StatusCode::RangeNotSatisfiable,
+                            "Requested range is not satisfiable.")
+                            .with_header(ContentRange(spec));
+                        return Err(err)
+                    },
+                }
+            },
+            _ => None,
+        };
+        let (start, end) = range.unwrap_or((0, len.saturating_sub(1)));
+        let content_len = if len == 0 { 0 } else { end - start + 1 };
+        if range.is_some() {
+            let spec = ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(len),
+            };
+            res.set_status(StatusCode::PartialContent);
+            res.set_header(ContentRange(spec));
+        }
+        res.set_header(ContentLength(content_len));
+        if method == Method::Head {
+            return Ok(res)
+        }
+        let mut file = File::open(&path).map_err(gen_io_error)?;
+        file.seek(SeekFrom::Start(start)).map_err(gen_io_error)?;
+        Ok(res.with_body_reader(file.take(content_len)))
+    }
+    fn info(&self) -> RouteInfo {
+        RouteInfo::new(self.name, RouteKind::Api)
+            .with_methods(vec![Method::Get, Method::Head])
+    }
+}
+
+/// Redirect to the path with trailing slash.
+fn redirect_to_dir(req: &Request) -> Response {
+    // Leading slashes are collapsed, or `//host` would be a redirection to
+    // another host.
+    let path = req.raw_path().trim_start_matches(['/', '\\']);
+    let location = if req.query.is_empty() {
+        format!("/{}/", path)
+    } else {
+        format!("/{}/?{}", path, req.query)
+    };
+    Response::moved_permanently(&location)
+}
+/// Whether the cached copy of client is still valid.
+fn is_not_modified(req: &Request, etag: &EntityTag, modified: Option<SystemTime>) -> bool {
+    // `If-Modified-Since` is ignored if `If-None-Match` is given.
+    if let Some(if_none_match) = req.header::<IfNoneMatch>() {
+        return match *if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(ref items) => items.iter().any(|x| x.weak_eq(etag)),
+        }
+    }
+    match (req.header::<IfModifiedSince>(), modified) {
+        (Some(&IfModifiedSince(since)), Some(modified)) => {
+            to_secs(modified) <= to_secs(since.into())
+        },
+        _ => false,
+    }
+}
+/// Whether the partial copy of client is the same as the current one, so that
+/// a range of it can be requested.
+fn is_range_fresh(req: &Request, etag: &EntityTag, modified: Option<SystemTime>) -> bool {
+    match req.header::<IfRange>() {
+        None => true,
+        Some(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Some(&IfRange::Date(date)) => {
+            modified.is_some_and(|x| to_secs(x) <= to_secs(date.into()))
+        },
+    }
+}
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+fn gen_etag(len: u64, modified: Option<SystemTime>) -> EntityTag {
+    let modified = modified.and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs() * 1_000_000_000 + u64::from(x.subsec_nanos()))
+        .unwrap_or_default();
+    EntityTag::strong(format!("{:x}-{:x}", len, modified))
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    path.canonicalize().map_err(gen_io_error)
+}
+fn metadata(path: &Path) -> Result<Metadata> {
+    fs::metadata(path).map_err(gen_io_error)
+}
+fn gen_io_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => gen_file_not_found(),
+        io::ErrorKind::PermissionDenied => {
+            Error::forbidden("Unable to access the requested file.")
+        },
+        _ => Error::internal("Unable to read the requested file.").with_cause(err),
+    }
+}
+fn gen_file_not_found() -> Error {
+    Error::not_found("Unable to find the requested file.")
+}
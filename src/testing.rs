@@ -0,0 +1,276 @@
+//! In-process testing of Writium applications.
+//!
+//! `TestClient` routes requests through a `Writium`, or any `Api`, without a
+//! server; requests are built fluently and responses are fully received before
+//! returned. Assertions of `TestResponse` panic with the status and body of the
+//! response, so they can be used in tests directly.
+//!
+//! Responses streaming endlessly, e.g., event streams, never finish
+//! receiving; such APIs should be tested on their own.
+use std::borrow::Cow;
+use std::fmt::Debug;
+use futures::{Future, Stream};
+use hyper::header::{ContentLength, ContentType, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use serde_qs;
+use proto::HyperRequest;
+use Writium;
+use prelude::*;
+
+/// Client routing requests in-process.
+#[derive(Clone)]
+pub struct TestClient {
+    writium: Writium,
+}
+impl TestClient {
+    /// Create a client routing requests through `writium`. Everything
+    /// configured on `writium`, e.g., middlewares and error renderers, takes
+    /// effect.
+    pub fn new(writium: Writium) -> TestClient {
+        TestClient { writium }
+    }
+    /// Create a client routing requests to `api`, which is bound to the root
+    /// of a default `Writium`.
+    pub fn for_api<A: Api + 'static>(api: A) -> TestClient {
+        let mut writium = Writium::new();
+        writium.bind(api);
+        TestClient::new(writium)
+    }
+
+    /// Start building a request of `method` to `path`. The path must not
+    /// include the query; see `TestRequest`'s `with_query()`.
+    pub fn request(&self, method: Method, path: &str) -> TestRequest {
+        TestRequest {
+            writium: self.writium.clone(),
+            method,
+            path: path.to_owned(),
+            query: None,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(Method::Get, path)
+    }
+    pub fn head(&self, path: &str) -> TestRequest {
+        self.request(Method::Head, path)
+    }
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(Method::Post, path)
+    }
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request(Method::Put, path)
+    }
+    pub fn patch(&self, path: &str) -> TestRequest {
+        self.request(Method::Patch, path)
+    }
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request(Method::Delete, path)
+    }
+    pub fn options(&self, path: &str) -> TestRequest {
+        self.request(Method::Options, path)
+    }
+}
+
+/// Request to be routed by `TestClient`.
+pub struct TestRequest {
+    writium: Writium,
+    method: Method,
+    path: String,
+    query: Option<String>,
+    headers: Headers,
+    body: Vec<u8>,
+}
+impl TestRequest {
+    /// Set the query string, without the leading `?`.
+    pub fn with_query(mut self, query: &str) -> Self {
+        self.query = Some(query.to_owned());
+        self
+    }
+    /// Set the query string serialized from `params`. Panics if `params`
+    /// cannot be serialized into a query string.
+    pub fn with_params<T: Serialize>(self, params: &T) -> Self {
+        let query = serde_qs::to_string(params)
+            .unwrap_or_else(|err| panic!("unable to serialize query: {}", err));
+        self.with_query(&query)
+    }
+    pub fn with_header<H: Header>(mut self, header: H) -> Self {
+        self.headers.set(header);
+        self
+    }
+    pub fn with_headers(mut self, headers: Headers) -> Self {
+        self.headers.extend(headers.iter());
+        self
+    }
+    /// Set a header by its name and value as-is, e.g., for headers having no
+    /// typed representation.
+    pub fn with_raw_header<K>(mut self, name: K, value: &str) -> Self
+        where K: Into<Cow<'static, str>> {
+        self.headers.set_raw(name, value.to_owned());
+        self
+    }
+    /// Set the body. `Content-Type` is left as-is.
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+    /// Set the body serialized from `json`, with `Content-Type` of JSON.
+    /// Panics if `json` cannot be serialized.
+    pub fn with_json<T: Serialize>(self, json: &T) -> Self {
+        let body = serde_json::to_vec(json)
+            .unwrap_or_else(|err| panic!("unable to serialize JSON: {}", err));
+        self.with_header(ContentType::json())
+            .with_body(body)
+    }
+    /// Set the body serialized from `form`, with `Content-Type` of URL-encoded
+    /// form data. Panics if `form` cannot be serialized.
+    pub fn with_form<T: Serialize>(self, form: &T) -> Self {
+        let body = serde_qs::to_string(form)
+            .unwrap_or_else(|err| panic!("unable to serialize form: {}", err));
+        self.with_header(ContentType::form_url_encoded())
+            .with_body(body)
+    }
+
+    /// Route the request and wait for the response. `Content-Length` is set
+    /// if the body is not empty.
+    ///
+    /// Panics if the path and query don't make a valid URI, or the response
+    /// body failed to be received.
+    pub fn send(self) -> TestResponse {
+        let uri = match self.query {
+            Some(ref query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        };
+        let uri = uri.parse::<Uri>()
+            .unwrap_or_else(|err| panic!("invalid URI `{}`: {}", uri, err));
+        let mut req = HyperRequest::new(self.method, uri);
+        let mut headers = self.headers;
+        if !self.body.is_empty() && !headers.has::<ContentLength>() {
+            headers.set(ContentLength(self.body.len() as u64));
+        }
+        *req.headers_mut() = headers;
+        req.set_body(self.body);
+        let res = self.writium.route(req)
+            .wait()
+            .unwrap_or_else(|err| panic!("unable to route request: {}", err));
+        let response = Response::new()
+            .with_status(res.status())
+            .with_headers(res.headers().clone());
+        let body = res.body()
+            .concat2()
+            .wait()
+            .unwrap_or_else(|err| panic!("unable to receive response body: {}", err));
+        TestResponse {
+            response: response.with_body(body.to_vec()),
+        }
+    }
+}
+
+/// Response received by `TestClient`, with the body fully received.
+///
+/// Assertions return the response itself, so they can be chained.
+#[derive(Debug)]
+pub struct TestResponse {
+    response: Response,
+}
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+    pub fn header<H: Header>(&self) -> Option<&H> {
+        self.response.header::<H>()
+    }
+    pub fn headers(&self) -> &Headers {
+        self.response.headers()
+    }
+    pub fn body(&self) -> &[u8] {
+        self.response.body()
+    }
+    /// Body as text. Panics if the body is not valid UTF-8.
+    pub fn text(&self) -> &str {
+        ::std::str::from_utf8(self.body())
+            .unwrap_or_else(|err| panic!("body is not valid UTF-8: {}\n{}", err,
+                self.describe()))
+    }
+    /// Body deserialized from JSON. Panics if the body cannot be deserialized
+    /// into `T`.
+    pub fn json<'de, T: Deserialize<'de>>(&'de self) -> T {
+        serde_json::from_slice(self.body())
+            .unwrap_or_else(|err| panic!("unable to deserialize JSON body: {}\n{}",
+                err, self.describe()))
+    }
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+    pub fn into_response(self) -> Response {
+        self.response
+    }
+
+    /// Assert the status is `status`.
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        if self.status() != status {
+            panic!("expected status `{}`, got:\n{}", status, self.describe());
+        }
+        self
+    }
+    /// Assert the header is present and equal to `header`.
+    pub fn assert_header<H>(&self, header: H) -> &Self
+        where H: Header + PartialEq + Debug {
+        match self.header::<H>() {
+            Some(x) if *x == header => {},
+            Some(x) => panic!("expected header `{}: {:?}`, got `{:?}`:\n{}",
+                H::header_name(), header, x, self.describe()),
+            None => panic!("expected header `{}: {:?}`, got none:\n{}",
+                H::header_name(), header, self.describe()),
+        }
+        self
+    }
+    /// Assert the header of `name` is present with the value of `value`.
+    pub fn assert_raw_header(&self, name: &str, value: &str) -> &Self {
+        let actual = self.headers().get_raw(name)
+            .and_then(|raw| raw.one())
+            .map(String::from_utf8_lossy);
+        match actual {
+            Some(ref x) if x == value => {},
+            Some(x) => panic!("expected header `{}: {}`, got `{}`:\n{}",
+                name, value, x, self.describe()),
+            None => panic!("expected header `{}: {}`, got none:\n{}",
+                name, value, self.describe()),
+        }
+        self
+    }
+    /// Assert the header is absent.
+    pub fn assert_no_header<H: Header>(&self) -> &Self {
+        if self.headers().has::<H>() {
+            panic!("expected no header `{}`:\n{}", H::header_name(),
+                self.describe());
+        }
+        self
+    }
+    /// Assert the body is `body`.
+    pub fn assert_body<B: AsRef<[u8]>>(&self, body: B) -> &Self {
+        if self.body() != body.as_ref() {
+            panic!("expected body `{}`, got:\n{}",
+                String::from_utf8_lossy(body.as_ref()), self.describe());
+        }
+        self
+    }
+    /// Assert the body is JSON equal to `json`. Objects are compared
+    /// regardless of the order of members.
+    pub fn assert_json<T: Serialize>(&self, json: &T) -> &Self {
+        let expected = serde_json::to_value(json)
+            .unwrap_or_else(|err| panic!("unable to serialize JSON: {}", err));
+        let actual: Value = self.json();
+        if actual != expected {
+            panic!("expected JSON body `{}`, got:\n{}", expected, self.describe());
+        }
+        self
+    }
+
+    /// Status, headers and body for failure messages.
+    fn describe(&self) -> String {
+        format!("{}\n{}\n{}", self.status(), self.headers(),
+            String::from_utf8_lossy(self.body()))
+    }
+}
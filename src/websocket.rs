@@ -0,0 +1,510 @@
+use std::io::{self, Read, Write};
+use std::str;
+use std::sync::{Arc, Mutex};
+use base64;
+use bytes::Bytes;
+use futures::{AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use futures::Async::{NotReady, Ready};
+use hyper::header::Allow;
+use sha1::Sha1;
+use tokio_io::{AsyncRead, AsyncWrite};
+use prelude::*;
+
+/// Key of request extra where `Writium` puts the slot of pending upgrade, if
+/// the connection can be upgraded.
+pub(crate) const UPGRADE_EXTRA_KEY: &str = "writium.upgrade";
+/// GUID concatenated to `Sec-WebSocket-Key` to make `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Size of write buffer, over which sending is deferred till flushed.
+const WRITE_BUF_SIZE: usize = 64 * 1024;
+/// Maximum size of close reason in bytes, so that close frames fit in 125
+/// bytes with the status code.
+const MAX_REASON_SIZE: usize = 123;
+
+/// Upgrade accepted by an API, waiting for the response to be sent.
+pub(crate) struct Pending {
+    session: Session,
+    protocol: Option<String>,
+}
+impl Pending {
+    /// Start the session on the upgraded connection.
+    pub(crate) fn start<I>(self, io: I, read_buf: Bytes)
+        -> Box<dyn Future<Item=(), Error=()>>
+        where I: 'static + AsyncRead + AsyncWrite {
+        let ws = WebSocket::from_io(io, &read_buf, self.protocol);
+        (self.session.0)(ws)
+    }
+}
+pub(crate) type UpgradeSlot = Arc<Mutex<Option<Pending>>>;
+
+/// What to do with a WebSocket connection once the handshake is done. The
+/// returned future is run on the event loop serving the connection.
+pub struct Session(Box<dyn FnOnce(WebSocket) -> Box<dyn Future<Item=(), Error=()>> + Send>);
+impl Session {
+    pub fn new<F, R>(f: F) -> Session
+        where F: 'static + FnOnce(WebSocket) -> R + Send,
+              R: 'static + IntoFuture<Item=(), Error=()>,
+              R::Future: 'static {
+        Session(Box::new(move |ws| Box::new(f(ws).into_future())))
+    }
+}
+
+/// # WebSocket API
+///
+/// `WebSocketApi` accepts WebSocket upgrades on its route. It has to be wrapped
+/// in `Upgrade` before binding; or use `Namespace`'s `bind_websocket()`.
+///
+/// Handshakes are validated before `accept()` is called, and answered with
+/// `101 Switching Protocols` once accepted. The connection is handed to the
+/// `Session` after the response is sent. If the response is replaced, e.g., by
+/// middlewares, the session is dropped without being started.
+///
+/// Connections can only be upgraded when they are served by `Writium`'s
+/// `serve_connection()`. Otherwise handshakes are rejected with
+/// `501 Not Implemented`.
+pub trait WebSocketApi: 'static + Send + Sync {
+    /// Name of API. See `Api`'s `name()` for more information.
+    fn name(&self) -> &[&str];
+
+    /// Subprotocols supported, in order of preference. The first one offered
+    /// by the client is selected.
+    fn protocols(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Accept the handshake request with a `Session`, or reject it with an
+    /// error. Everything needed by the session should be taken from the
+    /// request in advance.
+    fn accept(&self, req: &mut Request) -> Result<Session>;
+
+    /// Metadata of the API used to generate documents. See module `openapi`.
+    fn doc(&self) -> Option<ApiDoc> {
+        None
+    }
+}
+
+/// Adapter making a `WebSocketApi` an `Api`.
+pub struct Upgrade<A: WebSocketApi>(pub A);
+impl<A: WebSocketApi> Api for Upgrade<A> {
+    fn name(&self) -> &[&str] {
+        self.0.name()
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let key = validate(req)?;
+        let slot = req.extra::<UpgradeSlot>(UPGRADE_EXTRA_KEY)
+            .cloned()
+            .ok_or_else(|| Error::new(StatusCode::NotImplemented,
+                "WebSocket is not supported by the server."))?;
+        let protocol = negotiate(req, self.0.protocols());
+        let session = self.0.accept(req)?;
+        let mut res = Response::new()
+            .with_status(StatusCode::SwitchingProtocols);
+        {
+            let headers = res.headers_mut();
+            headers.set_raw("Upgrade", "websocket");
+            headers.set_raw("Connection", "Upgrade");
+            headers.set_raw("Sec-WebSocket-Accept", accept_key(&key));
+            if let Some(ref protocol) = protocol {
+                headers.set_raw("Sec-WebSocket-Protocol", protocol.clone());
+            }
+        }
+        let mut slot = slot.lock().unwrap_or_else(|err| err.into_inner());
+        *slot = Some(Pending { session, protocol });
+        Ok(res)
+    }
+    fn info(&self) -> RouteInfo {
+        RouteInfo::new(self.name(), RouteKind::Api)
+            .with_methods(vec![Method::Get])
+            .with_doc(self.doc())
+    }
+    fn doc(&self) -> Option<ApiDoc> {
+        self.0.doc()
+    }
+}
+
+/// Whether a comma-separated header contains the token, case-insensitively.
+fn has_token(req: &Request, name: &str, token: &str) -> bool {
+    req.headers.get_raw(name)
+        .is_some_and(|raw| raw.iter()
+            .filter_map(|line| str::from_utf8(line).ok())
+            .flat_map(|line| line.split(','))
+            .any(|x| x.trim().eq_ignore_ascii_case(token)))
+}
+/// Validate the handshake request and get `Sec-WebSocket-Key`.
+fn validate(req: &Request) -> Result<String> {
+    if req.method() != Method::Get {
+        return Err(Error::method_not_allowed()
+            .with_header(Allow(vec![Method::Get])))
+    }
+    let version = req.headers.get_raw("Sec-WebSocket-Version")
+        .and_then(|x| x.one());
+    if !has_token(req, "Upgrade", "websocket") ||
+        !has_token(req, "Connection", "upgrade") ||
+        version != Some(b"13") {
+        let mut err = Error::new(StatusCode::UpgradeRequired,
+            "WebSocket upgrade is required.");
+        err.headers_mut().set_raw("Upgrade", "websocket");
+        err.headers_mut().set_raw("Sec-WebSocket-Version", "13");
+        return Err(err)
+    }
+    req.headers.get_raw("Sec-WebSocket-Key")
+        .and_then(|x| x.one())
+        .and_then(|x| str::from_utf8(x).ok())
+        .map(|x| x.trim())
+        .filter(|x| base64::decode(x).is_ok_and(|x| x.len() == 16))
+        .map(|x| x.to_owned())
+        .ok_or_else(|| Error::bad_request("Invalid WebSocket key."))
+}
+/// Select the first supported subprotocol offered by the client.
+fn negotiate(req: &Request, protocols: &[&str]) -> Option<String> {
+    let offered: Vec<&str> = req.headers.get_raw("Sec-WebSocket-Protocol")
+        .map(|raw| raw.iter()
+            .filter_map(|line| str::from_utf8(line).ok())
+            .flat_map(|line| line.split(','))
+            .map(|x| x.trim())
+            .collect())
+        .unwrap_or_default();
+    protocols.iter()
+        .find(|x| offered.contains(x))
+        .map(|x| (*x).to_owned())
+}
+/// Make `Sec-WebSocket-Accept` from `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// WebSocket message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Pings are answered automatically, and are yielded only for
+    /// information.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Closing handshake with optional status code and reason. A received
+    /// close is answered automatically, after which no message is yielded.
+    ///
+    /// Only codes defined for use in close frames can be sent or received,
+    /// i.e. 1000-1003, 1007-1014 and 3000-4999. Reasons longer than 123 bytes
+    /// are truncated.
+    Close(Option<(u16, String)>),
+}
+
+/// Whether the status code can be used in close frames. 1004, 1005, 1006 and
+/// 1015 are reserved and must not appear on the wire.
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+/// Truncate the reason to fit in close frames, at a character boundary.
+fn truncate_reason(reason: &str) -> &str {
+    if reason.len() <= MAX_REASON_SIZE {
+        return reason
+    }
+    let mut end = MAX_REASON_SIZE;
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+trait Io: AsyncRead + AsyncWrite {}
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+/// Server side of a WebSocket connection. It's a `Stream` of received messages
+/// and a `Sink` of messages to send; use `split()` to handle both directions
+/// separately.
+pub struct WebSocket {
+    io: Box<dyn Io>,
+    protocol: Option<String>,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    /// Opcode and payload of the fragmented message being received.
+    fragments: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    close_sent: bool,
+    close_received: bool,
+}
+impl WebSocket {
+    /// Wrap a connection whose handshake is already done. `read_buf` contains
+    /// bytes read from the connection but not yet processed.
+    pub fn from_io<I>(io: I, read_buf: &[u8], protocol: Option<String>) -> WebSocket
+        where I: 'static + AsyncRead + AsyncWrite {
+        WebSocket {
+            io: Box::new(io),
+            protocol,
+            read_buf: read_buf.to_owned(),
+            write_buf: Vec::new(),
+            fragments: None,
+            max_message_size: 16 * 1024 * 1024,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+    /// Subprotocol negotiated in handshake.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+    /// Set the maximum size of received messages in bytes. The connection is
+    /// closed if a larger message is received. 16 MiB by default.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) {
+        let buf = &mut self.write_buf;
+        buf.push(0x80 | opcode);
+        let len = payload.len();
+        if len < 126 {
+            buf.push(len as u8);
+        } else if len <= 0xFFFF {
+            buf.push(126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.push(127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        buf.extend_from_slice(payload);
+    }
+    fn write_close(&mut self, code: u16, reason: &str) {
+        if self.close_sent {
+            return
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(truncate_reason(reason).as_bytes());
+        self.write_frame(0x8, &payload);
+        self.close_sent = true;
+    }
+    /// Close the connection for protocol violation.
+    fn fail(&mut self, code: u16, description: &'static str) -> Error {
+        self.write_close(code, description);
+        let _ = self.poll_complete();
+        self.close_received = true;
+        Error::bad_request(description)
+    }
+
+    /// Parse a message out of the read buffer. `None` is returned if more data
+    /// is needed.
+    fn parse(&mut self) -> Result<Option<Message>> {
+        loop {
+            let buf = &self.read_buf;
+            if buf.len() < 2 {
+                return Ok(None)
+            }
+            let fin = buf[0] & 0x80 != 0;
+            let opcode = buf[0] & 0x0F;
+            if buf[0] & 0x70 != 0 {
+                return Err(self.fail(1002, "Reserved bits are set."))
+            }
+            if buf[1] & 0x80 == 0 {
+                return Err(self.fail(1002, "Frames from client must be masked."))
+            }
+            let (len, mut pos): (u64, usize) = match buf[1] & 0x7F {
+                126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+                127 if buf.len() >= 10 => {
+                    if buf[2] & 0x80 != 0 {
+                        return Err(self.fail(1002, "Invalid payload length."))
+                    }
+                    let mut len = [0; 8];
+                    len.copy_from_slice(&buf[2..10]);
+                    (u64::from_be_bytes(len), 10)
+                },
+                126 | 127 => return Ok(None),
+                len => (len as u64, 2),
+            };
+            if opcode >= 0x8 && (!fin || len > 125) {
+                return Err(self.fail(1002, "Invalid control frame."))
+            }
+            let buffered = self.fragments.as_ref().map_or(0, |x| x.1.len());
+            let remaining = self.max_message_size.saturating_sub(buffered) as u64;
+            // The masking key follows the length.
+            let end = match (pos + 4).checked_add(len as usize) {
+                Some(end) if len <= remaining => end,
+                _ => return Err(self.fail(1009, "Message is too large.")),
+            };
+            if buf.len() < end {
+                return Ok(None)
+            }
+            let mut mask = [0; 4];
+            mask.copy_from_slice(&buf[pos..pos + 4]);
+            pos += 4;
+            let payload: Vec<u8> = buf[pos..end].iter()
+                .enumerate()
+                .map(|(i, x)| x ^ mask[i % 4])
+                .collect();
+            self.read_buf.drain(..end);
+
+            let (opcode, payload) = match (opcode, self.fragments.take()) {
+                (0x0, Some((opcode, mut data))) => {
+                    data.extend_from_slice(&payload);
+                    if !fin {
+                        self.fragments = Some((opcode, data));
+                        continue
+                    }
+                    (opcode, data)
+                },
+                (0x0, None) => return Err(self.fail(1002, "Unexpected continuation frame.")),
+                (0x1, None) | (0x2, None) if !fin => {
+                    self.fragments = Some((opcode, payload));
+                    continue
+                },
+                (0x1, Some(_)) | (0x2, Some(_)) => {
+                    return Err(self.fail(1002, "Expected continuation frame."))
+                },
+                (opcode, fragments) => {
+                    // Control frames can be interleaved with fragments.
+                    self.fragments = fragments;
+                    (opcode, payload)
+                },
+            };
+            let msg = match opcode {
+                0x1 => match String::from_utf8(payload) {
+                    Ok(text) => Message::Text(text),
+                    Err(_) => return Err(self.fail(1007, "Text is not UTF-8.")),
+                },
+                0x2 => Message::Binary(payload),
+                0x8 => {
+                    let close = match payload.len() {
+                        0 => None,
+                        1 => return Err(self.fail(1002, "Invalid close frame.")),
+                        _ => {
+                            let code = u16::from_be_bytes([payload[0], payload[1]]);
+                            if !is_valid_close_code(code) {
+                                return Err(self.fail(1002, "Invalid close code."))
+                            }
+                            match str::from_utf8(&payload[2..]) {
+                                Ok(reason) => Some((code, reason.to_owned())),
+                                Err(_) => return Err(self.fail(1007, "Reason is not UTF-8.")),
+                            }
+                        },
+                    };
+                    // Echo the status code as is suggested.
+                    let code = close.as_ref().map_or(1000, |x| x.0);
+                    self.write_close(code, "");
+                    self.close_received = true;
+                    Message::Close(close)
+                },
+                0x9 => {
+                    self.write_frame(0xA, &payload);
+                    Message::Ping(payload)
+                },
+                0xA => Message::Pong(payload),
+                _ => return Err(self.fail(1002, "Unknown opcode.")),
+            };
+            return Ok(Some(msg))
+        }
+    }
+}
+impl Stream for WebSocket {
+    type Item = Message;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Message>, Error> {
+        loop {
+            if self.close_received {
+                // Make sure the reply of close is sent.
+                try_ready!(self.poll_complete());
+                return Ok(Ready(None))
+            }
+            if let Some(msg) = self.parse()? {
+                return Ok(Ready(Some(msg)))
+            }
+            // Send automatic replies in time.
+            self.poll_complete()?;
+            let mut buf = [0; 4096];
+            match self.io.read(&mut buf) {
+                Ok(0) => return Ok(Ready(None)),
+                Ok(len) => self.read_buf.extend_from_slice(&buf[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(NotReady)
+                },
+                Err(err) => return Err(gen_io_error(err)),
+            }
+        }
+    }
+}
+impl Sink for WebSocket {
+    type SinkItem = Message;
+    type SinkError = Error;
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, Error> {
+        if self.write_buf.len() >= WRITE_BUF_SIZE {
+            self.poll_complete()?;
+            if self.write_buf.len() >= WRITE_BUF_SIZE {
+                return Ok(AsyncSink::NotReady(msg))
+            }
+        }
+        if self.close_sent {
+            return Err(Error::internal("WebSocket is closing."))
+        }
+        match msg {
+            Message::Text(text) => self.write_frame(0x1, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(0x2, &data),
+            Message::Ping(data) => self.write_frame(0x9, &data),
+            Message::Pong(data) => self.write_frame(0xA, &data),
+            Message::Close(Some((code, _))) if !is_valid_close_code(code) => {
+                return Err(Error::internal("Invalid close code."))
+            },
+            Message::Close(Some((code, reason))) => self.write_close(code, &reason),
+            Message::Close(None) => {
+                self.write_frame(0x8, &[]);
+                self.close_sent = true;
+            },
+        }
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        while !self.write_buf.is_empty() {
+            match self.io.write(&self.write_buf) {
+                Ok(0) => {
+                    let err = io::Error::new(io::ErrorKind::WriteZero, "connection closed");
+                    return Err(gen_io_error(err))
+                },
+                Ok(len) => { self.write_buf.drain(..len); },
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(NotReady)
+                },
+                Err(err) => return Err(gen_io_error(err)),
+            }
+        }
+        match self.io.flush() {
+            Ok(()) => Ok(Ready(())),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(NotReady),
+            Err(err) => Err(gen_io_error(err)),
+        }
+    }
+    fn close(&mut self) -> Poll<(), Error> {
+        self.write_close(1000, "");
+        try_ready!(self.poll_complete());
+        self.io.shutdown().map_err(gen_io_error)
+    }
+}
+
+fn gen_io_error(err: io::Error) -> Error {
+    Error::internal("WebSocket connection failed.").with_cause(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_codes() {
+        for &code in &[1000, 1003, 1007, 1014, 3000, 4999] {
+            assert!(is_valid_close_code(code), "{}", code);
+        }
+        for &code in &[0, 999, 1004, 1005, 1006, 1015, 2999, 5000] {
+            assert!(!is_valid_close_code(code), "{}", code);
+        }
+    }
+    #[test]
+    fn reasons_are_truncated_at_char_boundary() {
+        assert_eq!(truncate_reason("bye"), "bye");
+        let reason = "a".repeat(200);
+        assert_eq!(truncate_reason(&reason).len(), 123);
+        // 'é' takes 2 bytes, so the 62nd one crosses the limit.
+        let reason = "é".repeat(100);
+        assert_eq!(truncate_reason(&reason), "é".repeat(61));
+    }
+}
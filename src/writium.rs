@@ -1,6 +1,22 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 use proto::{HyperRequest, HyperResponse};
-use futures::Future;
+use futures::{future, Future, Stream};
+use hyper::Body;
+use hyper::header::{Accept, ContentLength, Date, RetryAfter, Server};
+use hyper::server::{Http, Service};
+use futures_cpupool::CpuPool;
+use tokio_io::{AsyncRead, AsyncWrite};
+use introspect::ROOT_EXTRA_KEY;
+use proto::BodyStream;
+use proto::response::{allows_body, new_stream_pool};
+use pool::{Overload, Pool};
+use api::RouteFuture;
+use error::ErrorFormat;
+use context::{given_request_id, ErrorContext, REQUEST_ID_HEADER};
+use render::{ErrorRenderer, Negotiate};
+use observe::{ErrorObserver, Log};
+use websocket::{UpgradeSlot, UPGRADE_EXTRA_KEY};
 use prelude::*;
 
 /// The element Writium.
@@ -8,65 +24,416 @@ use prelude::*;
 /// Writium holds all the APIs and transform `hyper` data types into what
 /// Writium APIs can utilize. Itself can be considered a `Namespace` interfacing
 /// `hyper`-variant frameworks.
+///
+/// `Writium` is cheap to clone; clones share the same APIs.
+#[derive(Clone)]
 pub struct Writium {
     ns: Arc<Namespace>,
+    pool: Option<Arc<Pool>>,
+    stream_pool: Option<CpuPool>,
+    server: Option<Arc<str>>,
+    error_format: ErrorFormat,
+    renderer: Arc<dyn ErrorRenderer>,
+    observer: Arc<dyn ErrorObserver>,
 }
 impl Writium {
     /// Create a new instance of `Writium`.
     pub fn new() -> Writium {
         Writium {
             ns: Arc::new(Namespace::new(&[])),
+            pool: None,
+            stream_pool: None,
+            server: None,
+            error_format: ErrorFormat::Problem,
+            renderer: Arc::new(Negotiate),
+            observer: Arc::new(Log),
         }
     }
 
     /// Route a `HyperRequest` to target API and return a `Future` of
     /// `HyperResponse`.
+    ///
+    /// If the request body is larger than the limit of the target API, the
+    /// body is not (or no longer) received and `413 Payload Too Large` is
+    /// responded.
+    ///
+    /// Responses are normalized before returned: `Content-Length` of buffered
+    /// bodies, `Date`, `Server` (if set) and `X-Request-Id` are added if
+    /// missing; bodies are
+    /// stripped for `HEAD` requests and statuses not allowing bodies (1xx,
+    /// `204 No Content` and `304 Not Modified`). The HTTP version of responses
+    /// is left to the server, which follows the request.
+    ///
+    /// `HEAD` requests failed with `405 Method Not Allowed` are routed again as
+    /// `GET`, so that APIs don't have to handle `HEAD` themselves.
     pub fn route(&self, req: HyperRequest)
-        -> Box<Future<Item=HyperResponse, Error=::hyper::Error>> {
-
-        // TODO: Deal with that path segments are not parsed.
+        -> Box<dyn Future<Item=HyperResponse, Error=::hyper::Error>> {
+        self.route_upgradable(req, None)
+    }
+    fn route_upgradable(&self, req: HyperRequest, upgrade: Option<UpgradeSlot>)
+        -> ResponseFuture {
         let (method, uri, _version, headers, body) = req.deconstruct();
-        use futures::Stream;
         let ns = self.ns.clone();
+        let pool = self.pool.clone();
+        let mut ctx = ErrorContext::new(method.clone(), uri.path())
+            .with_format(self.error_format);
+        if let Some(request_id) = given_request_id(&headers) {
+            ctx = ctx.with_request_id(request_id);
+        }
+        if let Some(accept) = headers.get::<Accept>() {
+            ctx = ctx.with_accept(accept.clone());
+        }
+        let out = Output {
+            ctx,
+            stream_pool: self.stream_pool.clone(),
+            server: self.server.clone(),
+            renderer: self.renderer.clone(),
+            observer: self.observer.clone(),
+        };
+        let mut req = Request::new(method);
+        req.request_id = out.ctx.request_id().to_owned();
+        req.set_headers(headers);
+        req.set_extra(ROOT_EXTRA_KEY, ns.clone());
+        if let Some(upgrade) = upgrade {
+            req.set_extra(UPGRADE_EXTRA_KEY, upgrade);
+        }
+        if let Err(err) = req.try_set_uri(&uri) {
+            // Segments are decoded lossily, so that the error can be handled
+            // where the request would be routed.
+            req.set_uri(&uri);
+            let res = ns.route_error(&mut req, err);
+            return Box::new(future::ok(out.finish(res)))
+        }
+        let limit = ns.body_limit(req.path_segs());
+        if let (Some(limit), Some(&ContentLength(len))) =
+            (limit, req.header::<ContentLength>()) {
+            if len > limit as u64 {
+                let res = ns.route_error(&mut req, gen_payload_too_large());
+                return Box::new(future::ok(out.finish(res)))
+            }
+        }
+        if ns.stream_body(req.path_segs()) {
+            let mut received = 0;
+            let stream = body
+                .map_err(|err| Error::bad_request("Unable to receive request body.").with_cause(err))
+                .and_then(move |chunk| {
+                    received += chunk.len();
+                    if limit.is_some_and(|x| received > x) {
+                        Err(gen_payload_too_large())
+                    } else {
+                        Ok(chunk)
+                    }
+                });
+            req.set_body_stream(BodyStream::new(stream));
+            return route_inline(&ns, req, out)
+        }
         let f_res = body
-            .concat2()
-            .map(move |body| {
-                let mut req = Request::new(method);
-                req.set_headers(headers);
-                req.set_body(body.to_vec());
-                req.set_uri(&uri);
-                // No need to check namespace name. Safe to route
-                // directly.
-                match ns.route(&mut req) {
-                    Ok(res) => res.into(),
-                    Err(err) => {
-                        // Log if error occurred.
-                        let mut log = Vec::<String>::new();
-                        if err.status().is_server_error() {
-                            log.push(format!("Unexpected error occured: {}", err));
-                            let mut err: &::std::error::Error = &err;
-                            loop {
-                                if let Some(cause) = err.cause() {
-                                    log.push(format!("\tBy: {}", cause));
-                                    err = cause;
-                                } else {
-                                    break
-                                }
-                            }
-                            warn!("{}", log.join("\n"));
-                        } else if err.status().is_client_error() {
-                            warn!("Bad request induced an error: {}", err);
+            // `None` indicates the body exceeded the limit.
+            .map_err(Some)
+            .fold(Vec::new(), move |mut buf, chunk| {
+                if limit.is_some_and(|x| buf.len() + chunk.len() > x) {
+                    return Err(None)
+                }
+                buf.extend_from_slice(&chunk);
+                Ok(buf)
+            })
+            .then(move |body| -> Box<dyn Future<Item=HyperResponse, Error=::hyper::Error>> {
+                match body {
+                    Ok(body) => {
+                        req.set_body(body);
+                        match pool {
+                            Some(ref pool) if !ns.is_async(req.path_segs()) => {
+                                route_in_pool(pool, ns, req, out)
+                            },
+                            _ => route_inline(&ns, req, out),
                         }
-                        err.into()
                     },
+                    Err(None) => {
+                        let res = ns.route_error(&mut req, gen_payload_too_large());
+                        Box::new(future::ok(out.finish(res)))
+                    },
+                    Err(Some(err)) => Box::new(future::err(err)),
                 }
             });
         Box::new(f_res)
     }
 
+    /// Serve HTTP on a connection, routing requests to the binded APIs. Unlike
+    /// serving with `hyper` directly, connections served this way can be
+    /// upgraded to WebSocket. See `WebSocketApi` for more information.
+    ///
+    /// The returned future resolves when the connection is closed, or when the
+    /// WebSocket session ends if the connection is upgraded.
+    pub fn serve_connection<I>(&self, io: I)
+        -> Box<dyn Future<Item=(), Error=::hyper::Error>>
+        where I: 'static + AsyncRead + AsyncWrite {
+        let slot = UpgradeSlot::default();
+        let service = Upgradable {
+            writium: self.clone(),
+            slot: slot.clone(),
+        };
+        let mut conn = Some(Http::<Chunk>::new().serve_connection(io, service));
+        let f_res = future::poll_fn(move || {
+                // The connection is taken only once it's done.
+                try_ready!(conn.as_mut().expect("polled after completion").poll_without_shutdown());
+                Ok(conn.take().expect("polled after completion").into_parts().into())
+            })
+            .and_then(move |parts| {
+                let pending = slot.lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .take();
+                match pending {
+                    Some(pending) => pending.start(parts.io, parts.read_buf),
+                    None => Box::new(future::ok(())),
+                }.then(|_| Ok(()))
+            });
+        Box::new(f_res)
+    }
+
+    /// Route buffered requests on the worker threads of the given pool. See
+    /// `Pool` for more information.
+    ///
+    /// APIs are routed synchronously on worker threads, i.e., `Api`'s
+    /// `route()` is called instead of `route_async()`. Requests to
+    /// asynchronous APIs (see `Api`'s `is_async()`) are routed on the current
+    /// thread as if there is no pool, as their futures don't block and may
+    /// rely on the event loop; so are requests with body streams.
+    pub fn set_pool(&mut self, pool: Pool) {
+        self.pool = Some(Arc::new(pool));
+    }
+
+    /// Set the number of threads sending streamed response bodies, i.e., those
+    /// set by `Response`'s `with_body_stream()` or `with_body_reader()`. Polls
+    /// of the streams and reads of the readers block these threads, so slow
+    /// ones delay the others. By default, streams of all `Writium`s are sent
+    /// on a shared pool of as many threads as CPUs.
+    ///
+    /// Panics if `threads` is 0.
+    pub fn set_stream_threads(&mut self, threads: usize) {
+        assert!(threads > 0, "no thread to send streams");
+        self.stream_pool = Some(new_stream_pool(Some(threads)));
+    }
+
+    /// Set the value of `Server` header added to responses, unless APIs have
+    /// set one. No `Server` header is added by default.
+    pub fn set_server(&mut self, server: &str) {
+        self.server = Some(Arc::from(server));
+    }
+
+    /// Set the format of error response bodies. Errors are rendered as problem
+    /// details by default; use `ErrorFormat::Legacy` to keep the `msg` form for
+    /// existing clients. See `Error` for more information.
+    pub fn set_error_format(&mut self, format: ErrorFormat) {
+        self.error_format = format;
+    }
+
+    /// Set the renderer of errors, which can be overridden by namespaces.
+    /// Errors are rendered as JSON, HTML or plain text by content negotiation
+    /// by default. See module `render` for more information.
+    pub fn set_renderer<R: ErrorRenderer>(&mut self, renderer: R) {
+        self.renderer = Arc::new(renderer);
+    }
+
+    /// Set the observer notified of every error responded. Errors are logged
+    /// with `warn!` by default. See module `observe` for more information.
+    pub fn set_observer<O: ErrorObserver>(&mut self, observer: O) {
+        self.observer = Arc::new(observer);
+    }
+
+    /// Set the maximum size of request body in bytes for all APIs. It can be
+    /// overridden by namespaces. See `Namespace`'s `set_body_limit()` for more
+    /// information.
+    pub fn set_body_limit(&mut self, limit: usize) {
+        Arc::make_mut(&mut self.ns).set_body_limit(limit)
+    }
+
+    /// Set whether unknown fields are rejected in deserialization for all
+    /// APIs. It can be overridden by namespaces. See `Namespace`'s
+    /// `set_strict()` for more information.
+    pub fn set_strict(&mut self, strict: bool) {
+        Arc::make_mut(&mut self.ns).set_strict(strict)
+    }
+
+    /// Attach a middleware to the root namespace. See `Namespace`'s `attach()`
+    /// for more information.
+    pub fn attach<M: Middleware>(&mut self, middleware: M) {
+        Arc::make_mut(&mut self.ns).attach(middleware)
+    }
+
+    /// Describe all the binded APIs as a tree of routes.
+    pub fn routes(&self) -> RouteInfo {
+        self.ns.info()
+    }
+
     /// Bind an API to the root namespace. See `Namespace`'s `bind()` for more
     /// information.
     pub fn bind<A: Api + 'static>(&mut self, api: A) {
         Arc::make_mut(&mut self.ns).bind(api)
     }
+    /// Bind an asynchronous API to the root namespace. See `AsyncApi` for more
+    /// information.
+    pub fn bind_async<A: AsyncApi>(&mut self, api: A) {
+        Arc::make_mut(&mut self.ns).bind_async(api)
+    }
+    /// Bind an API accepting WebSocket upgrades to the root namespace. See
+    /// `WebSocketApi` for more information.
+    pub fn bind_websocket<A: WebSocketApi>(&mut self, api: A) {
+        Arc::make_mut(&mut self.ns).bind_websocket(api)
+    }
+}
+impl Default for Writium {
+    fn default() -> Writium {
+        Writium::new()
+    }
+}
+
+type ResponseFuture = Box<dyn Future<Item=HyperResponse, Error=::hyper::Error>>;
+
+/// Service routing requests of a connection which can be upgraded.
+struct Upgradable {
+    writium: Writium,
+    slot: UpgradeSlot,
+}
+impl Service for Upgradable {
+    type Request = HyperRequest;
+    type Response = HyperResponse;
+    type Error = ::hyper::Error;
+    type Future = ResponseFuture;
+    fn call(&self, req: HyperRequest) -> ResponseFuture {
+        let slot = self.slot.clone();
+        let f_res = self.writium.route_upgradable(req, Some(self.slot.clone()))
+            .map(move |res| {
+                // The upgrade accepted by an API is abandoned if middlewares or
+                // errors have replaced the response.
+                if res.status() != StatusCode::SwitchingProtocols {
+                    slot.lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .take();
+                }
+                res
+            });
+        Box::new(f_res)
+    }
 }
+
+fn route_inline(ns: &Arc<Namespace>, req: Request, out: Output) -> ResponseFuture {
+    let ns = ns.clone();
+    // No need to check namespace name. Safe to route directly.
+    let f_res = ns.route_async(req)
+        .and_then(move |(mut req, res)| -> RouteFuture {
+            if is_head_not_allowed(&req, &res) {
+                req.rewind(Method::Get);
+                ns.route_async(req)
+            } else {
+                Box::new(future::ok((req, res)))
+            }
+        })
+        .map(move |(_, res)| out.finish(res))
+        .map_err(|never| match never {});
+    Box::new(f_res)
+}
+fn route_in_pool(pool: &Pool, ns: Arc<Namespace>, mut req: Request, out: Output)
+    -> ResponseFuture {
+    let reservation = match pool.reserve() {
+        Some(x) => x,
+        None => return match pool.overload() {
+            Overload::Inline => route_inline(&ns, req, out),
+            Overload::Reject(delay) => {
+                let mut err = Error::service_unavailable("Server is too busy.");
+                if let Some(delay) = delay {
+                    err.set_header(RetryAfter::Delay(delay));
+                }
+                let res = ns.route_error(&mut req, err);
+                Box::new(future::ok(out.finish(res)))
+            },
+        },
+    };
+    let job = move || {
+        let mut res = ns.route(&mut req);
+        if is_head_not_allowed(&req, &res) {
+            req.rewind(Method::Get);
+            res = ns.route(&mut req);
+        }
+        out.finish(res)
+    };
+    let f_res = pool.spawn(reservation, job)
+        .map_err(|never| match never {});
+    Box::new(f_res)
+}
+
+/// Whether `HEAD` is not allowed for the request, in which case it's routed
+/// again as `GET`, as the response is the same except for the body.
+fn is_head_not_allowed(req: &Request, res: &ApiResult) -> bool {
+    req.method() == Method::Head &&
+        res.as_ref().is_err_and(|err| err.status() == StatusCode::MethodNotAllowed)
+}
+
+/// What's needed to make the output of a request.
+#[derive(Clone)]
+struct Output {
+    ctx: ErrorContext,
+    stream_pool: Option<CpuPool>,
+    server: Option<Arc<str>>,
+    renderer: Arc<dyn ErrorRenderer>,
+    observer: Arc<dyn ErrorObserver>,
+}
+impl Output {
+    /// Transform the routing result into normalized `HyperResponse`. Errors
+    /// are observed and rendered.
+    fn finish(&self, res: ApiResult) -> HyperResponse {
+        let strip_body = |status: StatusCode| {
+            *self.ctx.method() == Method::Head || !allows_body(status)
+        };
+        let mut res: HyperResponse = match res {
+            Ok(mut res) => {
+                // Don't bother sending the stream.
+                let is_stream_dropped = strip_body(res.status()) &&
+                    res.take_body_stream().is_some();
+                let is_len_unknown = is_stream_dropped &&
+                    !res.headers().has::<ContentLength>();
+                let mut res = match self.stream_pool {
+                    Some(ref pool) => res.into_hyper(pool),
+                    None => res.into(),
+                };
+                if is_len_unknown {
+                    res.headers_mut().remove::<ContentLength>();
+                }
+                res
+            },
+            Err(mut err) => {
+                if err.instance().is_none() {
+                    err.set_instance(self.ctx.path());
+                }
+                self.observer.observe(&err, &self.ctx);
+                let renderer = err.take_renderer()
+                    .unwrap_or_else(|| self.renderer.clone());
+                renderer.render(err, &self.ctx)
+            },
+        };
+        let status = res.status();
+        if strip_body(status) {
+            // `Content-Length` is kept for `HEAD` as it's what `GET` gets.
+            res.set_body(Body::empty());
+        }
+        if status.is_informational() || status == StatusCode::NoContent {
+            res.headers_mut().remove::<ContentLength>();
+        }
+        if !res.headers().has::<Date>() {
+            res.headers_mut().set(Date(SystemTime::now().into()));
+        }
+        if let Some(ref server) = self.server {
+            if !res.headers().has::<Server>() {
+                res.headers_mut().set(Server::new(server.to_string()));
+            }
+        }
+        if res.headers().get_raw(REQUEST_ID_HEADER).is_none() {
+            res.headers_mut().set_raw(REQUEST_ID_HEADER, self.ctx.request_id().to_owned());
+        }
+        res
+    }
+}
+
+fn gen_payload_too_large() -> Error {
+    Error::payload_too_large("Request body is too large.")
+}
+
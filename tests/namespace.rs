@@ -0,0 +1,177 @@
+extern crate writium;
+
+use writium::Writium;
+use writium::hyper::header::{qitem, Accept, ContentType, Location, Server,
+    TransferEncoding};
+use writium::namespace::TrailingSlash;
+use writium::prelude::*;
+use writium::render::{Json, PlainText};
+use writium::testing::TestClient;
+
+struct Page(&'static [&'static str]);
+impl Api for Page {
+    fn name(&self) -> &[&str] {
+        self.0
+    }
+    fn route(&self, _req: &mut Request) -> ApiResult {
+        Ok(Response::new().with_body("page"))
+    }
+}
+
+fn client(policy: TrailingSlash) -> TestClient {
+    let mut writium = Writium::new();
+    writium.bind(Namespace::new(&[])
+        .with_trailing_slash(policy)
+        .with_api(Page(&["a", "b"]))
+        .with_api(Page(&["a"]))
+        .with_api(Page(&["evil.com"]))
+        // Escaped, as names starting with backslash are taken as escapes.
+        .with_api(Page(&["\\\\evil.com"]))
+        .with_api(Page(&["assets"])));
+    TestClient::new(writium)
+}
+
+#[test]
+fn trailing_slash_is_added() {
+    client(TrailingSlash::Always).get("/a/b").with_query("x=1").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/a/b/?x=1"));
+    client(TrailingSlash::Always).post("/a").send()
+        .assert_status(StatusCode::PermanentRedirect)
+        .assert_header(Location::new("/a/"));
+    client(TrailingSlash::Always).get("/a/b/").send()
+        .assert_status(StatusCode::Ok);
+}
+
+#[test]
+fn trailing_slash_is_removed() {
+    client(TrailingSlash::Never).get("/a/b//").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/a/b"));
+    client(TrailingSlash::Never).get("/a").send()
+        .assert_status(StatusCode::Ok);
+}
+
+#[test]
+fn only_requests_to_apis_are_redirected() {
+    // Not found.
+    client(TrailingSlash::Always).get("/missing").send()
+        .assert_status(StatusCode::NotFound);
+    client(TrailingSlash::Never).get("/missing/").send()
+        .assert_status(StatusCode::NotFound);
+    // Paths under an API, e.g., files.
+    client(TrailingSlash::Always).get("/assets/main.css").send()
+        .assert_status(StatusCode::Ok);
+}
+
+fn nested_client(outer: TrailingSlash, inner: TrailingSlash) -> TestClient {
+    let mut writium = Writium::new();
+    writium.bind(Namespace::new(&[])
+        .with_trailing_slash(outer)
+        .with_api(Page(&["a"]))
+        .with_api(Namespace::new(&["inner"])
+            .with_trailing_slash(inner)
+            .with_api(Page(&["b"]))
+            .with_api(Page(&[])))
+        .with_api(Namespace::new(&["inherit"])
+            .with_api(Page(&["b"]))));
+    TestClient::new(writium)
+}
+
+#[test]
+fn inner_policy_overrides_outer() {
+    let client = nested_client(TrailingSlash::Always, TrailingSlash::Never);
+    client.get("/inner/b").send()
+        .assert_status(StatusCode::Ok);
+    client.get("/inner/b/").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/inner/b"));
+    client.get("/inner/").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/inner"));
+    client.get("/inner").send()
+        .assert_status(StatusCode::Ok);
+    client.get("/a").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/a/"));
+    // Sub-namespaces without a policy inherit it.
+    client.get("/inherit/b").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/inherit/b/"));
+    let client = nested_client(TrailingSlash::Never, TrailingSlash::Ignore);
+    client.get("/inner/b/").send()
+        .assert_status(StatusCode::Ok);
+    client.get("/inner/b").send()
+        .assert_status(StatusCode::Ok);
+}
+
+#[test]
+fn redirection_stays_on_host() {
+    for path in &["//evil.com", "///evil.com", "/\\evil.com"] {
+        client(TrailingSlash::Always).get(path).send()
+            .assert_status(StatusCode::MovedPermanently)
+            .assert_header(Location::new("/evil.com/"));
+    }
+    client(TrailingSlash::Never).get("//evil.com/").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_header(Location::new("/evil.com"));
+}
+
+/// Tag errors passing through.
+struct Tag;
+impl Middleware for Tag {
+    fn error(&self, _req: &Request, err: Error) -> ApiResult {
+        Err(err.with_header(Server::new("tagged")))
+    }
+}
+
+struct Upload;
+impl Api for Upload {
+    fn name(&self) -> &[&str] {
+        &["upload"]
+    }
+    fn route(&self, _req: &mut Request) -> ApiResult {
+        Ok(Response::new())
+    }
+}
+
+fn api_client() -> TestClient {
+    let mut writium = Writium::new();
+    writium.set_renderer(PlainText);
+    writium.bind(Namespace::new(&["api"])
+        .with_renderer(Json)
+        .with_body_limit(4)
+        .with_middleware(Tag)
+        .with_api(Upload));
+    writium.bind(Namespace::new(&["web"]).with_body_limit(4));
+    TestClient::new(writium)
+}
+
+#[test]
+fn errors_before_routing_reach_namespace() {
+    let html = Accept(vec![qitem("text/html".parse().unwrap())]);
+    let problem = ContentType("application/problem+json".parse().unwrap());
+    // Rejected by `Content-Length`.
+    api_client().post("/api/upload").with_header(html.clone())
+        .with_body("too large").send()
+        .assert_status(StatusCode::PayloadTooLarge)
+        .assert_header(problem.clone())
+        .assert_header(Server::new("tagged"));
+    // Rejected while receiving.
+    api_client().post("/api/upload").with_header(html.clone())
+        .with_header(TransferEncoding::chunked())
+        .with_body("too large").send()
+        .assert_status(StatusCode::PayloadTooLarge)
+        .assert_header(problem.clone());
+    // Invalid path.
+    api_client().get("/api/%FF").with_header(html.clone()).send()
+        .assert_status(StatusCode::BadRequest)
+        .assert_header(problem)
+        .assert_header(Server::new("tagged"));
+    // Other namespaces are not affected.
+    api_client().post("/web/upload").with_header(html)
+        .with_body("too large").send()
+        .assert_status(StatusCode::PayloadTooLarge)
+        .assert_header(ContentType::plaintext())
+        .assert_no_header::<Server>();
+}
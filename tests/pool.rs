@@ -0,0 +1,140 @@
+extern crate futures;
+extern crate writium;
+
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use futures::{future, Future};
+use writium::Writium;
+use writium::hyper::Request as HyperRequest;
+use writium::hyper::header::RetryAfter;
+use writium::pool::{Overload, Pool};
+use writium::prelude::*;
+use writium::testing::TestClient;
+
+/// Respond with the name of the current thread.
+fn thread_name() -> Response {
+    let name = thread::current().name().unwrap_or_default().to_owned();
+    Response::new().with_body(name)
+}
+
+/// Block until told to continue.
+struct Block {
+    entered: Mutex<Sender<()>>,
+    resume: Mutex<Receiver<()>>,
+}
+impl Api for Block {
+    fn name(&self) -> &[&str] {
+        &["block"]
+    }
+    fn route(&self, _req: &mut Request) -> ApiResult {
+        self.entered.lock().unwrap().send(()).unwrap();
+        self.resume.lock().unwrap().recv().unwrap();
+        Ok(thread_name())
+    }
+}
+
+struct Fast;
+impl Api for Fast {
+    fn name(&self) -> &[&str] {
+        &["fast"]
+    }
+    fn route(&self, _req: &mut Request) -> ApiResult {
+        Ok(thread_name())
+    }
+}
+
+struct Later;
+impl AsyncApi for Later {
+    fn name(&self) -> &[&str] {
+        &["later"]
+    }
+    fn route(&self, _req: &mut Request) -> ApiFuture {
+        Box::new(future::lazy(|| Ok(thread_name())))
+    }
+}
+
+fn serve(pool: Pool) -> (Writium, Receiver<()>, Sender<()>) {
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (resume_tx, resume_rx) = mpsc::channel();
+    let mut writium = Writium::new();
+    writium.set_pool(pool);
+    writium.bind(Block {
+        entered: Mutex::new(entered_tx),
+        resume: Mutex::new(resume_rx),
+    });
+    writium.bind(Fast);
+    writium.bind_async(Later);
+    (writium, entered_rx, resume_tx)
+}
+/// Occupy the only worker of the pool of `writium`, and run `f` while it's
+/// occupied.
+fn while_blocked<F: FnOnce(&TestClient)>(writium: Writium, entered: Receiver<()>,
+    resume: Sender<()>, f: F) {
+    let blocked = writium.clone();
+    let handle = thread::spawn(move || {
+        let req = HyperRequest::new(Method::Get, "/block".parse().unwrap());
+        blocked.route(req).wait().unwrap()
+    });
+    entered.recv_timeout(Duration::from_secs(5)).unwrap();
+    f(&TestClient::new(writium));
+    resume.send(()).unwrap();
+    assert_eq!(handle.join().unwrap().status(), StatusCode::Ok);
+}
+
+#[test]
+fn sync_apis_are_routed_on_workers() {
+    let (writium, _, _) = serve(Pool::new(1, 0));
+    let res = TestClient::new(writium).get("/fast").send();
+    res.assert_status(StatusCode::Ok);
+    assert!(res.text().starts_with("writium-worker-"), "{}", res.text());
+}
+
+#[test]
+fn async_apis_are_routed_inline() {
+    let (writium, _, _) = serve(Pool::new(1, 0));
+    let res = TestClient::new(writium).get("/later").send();
+    res.assert_status(StatusCode::Ok);
+    assert!(!res.text().starts_with("writium-worker-"), "{}", res.text());
+}
+
+#[test]
+fn overload_is_rejected() {
+    let pool = Pool::new(1, 0)
+        .with_overload(Overload::Reject(Some(Duration::from_secs(3))));
+    let (writium, entered, resume) = serve(pool);
+    while_blocked(writium.clone(), entered, resume, |client| {
+        client.get("/fast").send()
+            .assert_status(StatusCode::ServiceUnavailable)
+            .assert_header(RetryAfter::Delay(Duration::from_secs(3)));
+        // Asynchronous APIs don't need workers.
+        client.get("/later").send()
+            .assert_status(StatusCode::Ok);
+    });
+    // The place is given back.
+    TestClient::new(writium).get("/fast").send()
+        .assert_status(StatusCode::Ok);
+}
+
+#[test]
+fn overload_is_routed_inline() {
+    let (writium, entered, resume) = serve(Pool::new(1, 0).with_overload(Overload::Inline));
+    while_blocked(writium, entered, resume, |client| {
+        let res = client.get("/fast").send();
+        res.assert_status(StatusCode::Ok);
+        assert!(!res.text().starts_with("writium-worker-"), "{}", res.text());
+    });
+}
+
+#[test]
+fn pool_of_no_worker() {
+    let (writium, _, _) = serve(Pool::new(0, 8));
+    TestClient::new(writium).get("/fast").send()
+        .assert_status(StatusCode::ServiceUnavailable)
+        .assert_no_header::<RetryAfter>();
+    let (writium, _, _) = serve(Pool::new(0, 8).with_overload(Overload::Inline));
+    let res = TestClient::new(writium).get("/fast").send();
+    res.assert_status(StatusCode::Ok);
+    assert!(!res.text().starts_with("writium-worker-"), "{}", res.text());
+}
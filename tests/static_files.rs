@@ -0,0 +1,194 @@
+extern crate writium;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use writium::hyper::header::{ContentRange, ContentRangeSpec, ETag, EntityTag,
+    IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range};
+use writium::prelude::*;
+use writium::static_files::StaticFiles;
+use writium::testing::TestClient;
+
+static DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary directory of `root`, served as `/files`, and `outside`, which is
+/// never served. It's removed when dropped.
+struct Site {
+    dir: PathBuf,
+}
+impl Site {
+    fn new() -> Site {
+        let id = DIR_ID.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir()
+            .join(format!("writium-static-{}-{}", process::id(), id));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(root.join("hello.txt"), "Hello, world!").unwrap();
+        fs::write(root.join("docs").join("index.html"), "<a href=\"page.html\">").unwrap();
+        fs::write(dir.join("outside").join("secret.txt"), "classified").unwrap();
+        Site { dir }
+    }
+    fn root(&self) -> PathBuf {
+        self.dir.join("root")
+    }
+    fn client(&self) -> TestClient {
+        TestClient::for_api(StaticFiles::new(&["files"], self.root()))
+    }
+}
+impl Drop for Site {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn files_are_served() {
+    let site = Site::new();
+    let client = site.client();
+    client.get("/files/hello.txt").send()
+        .assert_status(StatusCode::Ok)
+        .assert_raw_header("Content-Type", "text/plain")
+        .assert_body("Hello, world!");
+    client.head("/files/hello.txt").send()
+        .assert_status(StatusCode::Ok)
+        .assert_raw_header("Content-Length", "13")
+        .assert_body("");
+    client.get("/files/missing.txt").send()
+        .assert_status(StatusCode::NotFound);
+    client.post("/files/hello.txt").send()
+        .assert_status(StatusCode::MethodNotAllowed);
+}
+
+#[test]
+fn traversal_is_rejected() {
+    let site = Site::new();
+    let client = site.client();
+    for path in &[
+        "/files/../outside/secret.txt",
+        "/files/%2E%2E/outside/secret.txt",
+        "/files/..%2Foutside%2Fsecret.txt",
+        "/files/..%5Coutside%5Csecret.txt",
+        "/files/docs/..%2F..%2Foutside%2Fsecret.txt",
+        "/files/hello.txt%00",
+    ] {
+        let res = client.get(path).send();
+        res.assert_status(StatusCode::NotFound);
+        assert!(!res.text().contains("classified"), "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_outside_root_are_not_followed() {
+    use std::os::unix::fs::symlink;
+    let site = Site::new();
+    symlink(site.dir.join("outside"), site.root().join("link")).unwrap();
+    symlink(site.dir.join("outside").join("secret.txt"), site.root().join("secret.txt"))
+        .unwrap();
+    symlink(site.root().join("hello.txt"), site.root().join("inside.txt")).unwrap();
+    let client = site.client();
+    client.get("/files/link/secret.txt").send()
+        .assert_status(StatusCode::NotFound);
+    client.get("/files/secret.txt").send()
+        .assert_status(StatusCode::NotFound);
+    client.get("/files/inside.txt").send()
+        .assert_status(StatusCode::Ok)
+        .assert_body("Hello, world!");
+}
+
+#[test]
+fn index_is_served_with_trailing_slash() {
+    let site = Site::new();
+    let client = site.client();
+    client.get("/files/docs/").send()
+        .assert_status(StatusCode::Ok)
+        .assert_raw_header("Content-Type", "text/html")
+        .assert_body("<a href=\"page.html\">");
+    client.get("/files/docs").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_raw_header("Location", "/files/docs/");
+    client.get("/files/docs").with_query("lang=en").send()
+        .assert_status(StatusCode::MovedPermanently)
+        .assert_raw_header("Location", "/files/docs/?lang=en");
+    client.get("/files/empty/").send()
+        .assert_status(StatusCode::NotFound);
+    TestClient::for_api(StaticFiles::new(&["files"], site.root()).with_index(&[]))
+        .get("/files/docs/").send()
+        .assert_status(StatusCode::NotFound);
+}
+
+#[test]
+fn single_range_is_served() {
+    let site = Site::new();
+    let client = site.client();
+    client.get("/files/hello.txt").with_header(Range::bytes(7, 11)).send()
+        .assert_status(StatusCode::PartialContent)
+        .assert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((7, 11)),
+            instance_length: Some(13),
+        }))
+        .assert_body("world");
+    client.get("/files/hello.txt").with_header(Range::bytes(20, 30)).send()
+        .assert_status(StatusCode::RangeNotSatisfiable)
+        .assert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: None,
+            instance_length: Some(13),
+        }));
+    // Multiple ranges are not supported.
+    client.get("/files/hello.txt")
+        .with_header(Range::bytes_multi(vec![(0, 1), (3, 4)])).send()
+        .assert_status(StatusCode::Ok)
+        .assert_body("Hello, world!");
+}
+
+#[test]
+fn range_is_served_only_if_fresh() {
+    let site = Site::new();
+    let client = site.client();
+    let res = client.get("/files/hello.txt").send();
+    let etag = res.header::<ETag>().unwrap().0.clone();
+    client.get("/files/hello.txt")
+        .with_header(Range::bytes(0, 4))
+        .with_header(IfRange::EntityTag(etag))
+        .send()
+        .assert_status(StatusCode::PartialContent)
+        .assert_body("Hello");
+    client.get("/files/hello.txt")
+        .with_header(Range::bytes(0, 4))
+        .with_header(IfRange::EntityTag(EntityTag::strong("stale".to_owned())))
+        .send()
+        .assert_status(StatusCode::Ok)
+        .assert_body("Hello, world!");
+}
+
+#[test]
+fn cached_copies_are_validated() {
+    let site = Site::new();
+    let client = site.client();
+    let res = client.get("/files/hello.txt").send();
+    let etag = res.header::<ETag>().unwrap().0.clone();
+    let modified = res.header::<LastModified>().unwrap().0;
+    client.get("/files/hello.txt")
+        .with_header(IfNoneMatch::Items(vec![etag]))
+        .send()
+        .assert_status(StatusCode::NotModified)
+        .assert_body("");
+    client.get("/files/hello.txt")
+        .with_header(IfNoneMatch::Items(vec![EntityTag::strong("stale".to_owned())]))
+        .send()
+        .assert_status(StatusCode::Ok);
+    client.get("/files/hello.txt")
+        .with_header(IfModifiedSince(modified))
+        .send()
+        .assert_status(StatusCode::NotModified);
+    // `If-Modified-Since` is ignored if `If-None-Match` is given.
+    client.get("/files/hello.txt")
+        .with_header(IfNoneMatch::Items(vec![EntityTag::strong("stale".to_owned())]))
+        .with_header(IfModifiedSince(modified))
+        .send()
+        .assert_status(StatusCode::Ok);
+}
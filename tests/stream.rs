@@ -0,0 +1,85 @@
+extern crate futures;
+extern crate writium;
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::thread;
+use futures::{stream, Async, Poll, Stream};
+use writium::Writium;
+use writium::prelude::*;
+use writium::testing::TestClient;
+
+/// Yield the name and ID of the thread polling it, then end.
+struct WhereAmI(bool);
+impl Stream for WhereAmI {
+    type Item = Chunk;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        if self.0 {
+            return Ok(Async::Ready(None))
+        }
+        self.0 = true;
+        let current = thread::current();
+        let chunk = format!("{} {:?}", current.name().unwrap_or_default(), current.id());
+        Ok(Async::Ready(Some(chunk.into())))
+    }
+}
+
+struct Streams;
+impl Api for Streams {
+    fn name(&self) -> &[&str] {
+        &[]
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let res = Response::new();
+        let res = match req.path_segs().first().map(|x| x.as_str()) {
+            Some("iter") => res.with_body_iter(vec!["a", "b", "c"]),
+            Some("reader") => res.with_body_reader(Cursor::new(vec![b'x'; 20000])),
+            Some("error") => {
+                let chunks = stream::iter_result(vec![
+                    Ok(Chunk::from("a")),
+                    Err(Error::internal("Broken.")),
+                ]);
+                res.with_body_stream(chunks)
+            },
+            _ => res.with_body_stream(WhereAmI(false)),
+        };
+        Ok(res)
+    }
+}
+
+fn client(threads: Option<usize>) -> TestClient {
+    let mut writium = Writium::new();
+    if let Some(threads) = threads {
+        writium.set_stream_threads(threads);
+    }
+    writium.bind(Streams);
+    TestClient::new(writium)
+}
+
+#[test]
+fn streams_are_sent() {
+    let client = client(None);
+    client.get("/iter").send()
+        .assert_status(StatusCode::Ok)
+        .assert_body("abc");
+    let res = client.get("/reader").send();
+    assert_eq!(res.body(), &[b'x'; 20000][..]);
+}
+
+#[test]
+#[should_panic(expected = "unable to receive response body")]
+fn stream_errors_abort_body() {
+    client(None).get("/error").send();
+}
+
+#[test]
+fn streams_are_sent_on_configured_threads() {
+    let client = client(Some(1));
+    let threads: HashSet<String> = (0..8)
+        .map(|_| client.get("/").send().text().to_owned())
+        .collect();
+    assert_eq!(threads.len(), 1);
+    let thread = threads.into_iter().next().unwrap();
+    assert!(thread.starts_with("writium-stream-"), "{}", thread);
+}
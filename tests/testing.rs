@@ -0,0 +1,162 @@
+#[macro_use]
+extern crate serde_json;
+extern crate writium;
+
+use std::collections::BTreeMap;
+use serde_json::Value;
+use writium::Writium;
+use writium::hyper::header::{ContentLength, ContentType, Server, UserAgent};
+use writium::prelude::*;
+use writium::testing::TestClient;
+
+/// Echo what is received as JSON.
+struct Echo;
+impl Api for Echo {
+    fn name(&self) -> &[&str] {
+        &["echo"]
+    }
+    fn route(&self, req: &mut Request) -> ApiResult {
+        let body = match req.header::<ContentType>() {
+            Some(ty) if ty.0.subtype() == "json" => req.to_json::<Value>()?,
+            Some(_) => json!(req.to_form::<BTreeMap<String, String>>()?),
+            None => Value::Null,
+        };
+        let query = req.to_param::<BTreeMap<String, String>>()?;
+        let mut res = Response::new().with_json(&json!({
+            "method": req.method().to_string(),
+            "query": query,
+            "body": body,
+        }))?;
+        if let Some(agent) = req.header::<UserAgent>() {
+            res.headers_mut().set_raw("X-Echo", agent.to_string());
+        }
+        Ok(res)
+    }
+}
+
+fn client() -> TestClient {
+    TestClient::for_api(Echo)
+}
+
+#[test]
+fn routes_through_writium() {
+    let mut writium = Writium::new();
+    writium.set_server("writium-test");
+    writium.bind(Namespace::new(&["api"]).with_api(Echo));
+    let client = TestClient::new(writium);
+    client.get("/api/echo").send()
+        .assert_status(StatusCode::Ok)
+        .assert_header(Server::new("writium-test"))
+        .assert_json(&json!({ "method": "GET", "query": {}, "body": null }));
+    client.get("/echo").send()
+        .assert_status(StatusCode::NotFound);
+}
+
+#[test]
+fn routes_to_api() {
+    let res = client().delete("/echo").send();
+    res.assert_status(StatusCode::Ok);
+    assert_eq!(res.json::<Value>()["method"], "DELETE");
+}
+
+#[test]
+fn sends_query() {
+    client().get("/echo").with_query("a=1&b=x").send()
+        .assert_json(&json!({ "method": "GET", "query": { "a": "1", "b": "x" }, "body": null }));
+    let mut params = BTreeMap::new();
+    params.insert("page", "2");
+    client().get("/echo").with_params(&params).send()
+        .assert_json(&json!({ "method": "GET", "query": { "page": "2" }, "body": null }));
+}
+
+#[test]
+fn sends_json() {
+    let body = json!({ "title": "Writium", "tags": ["a", "b"] });
+    client().post("/echo").with_json(&body).send()
+        .assert_status(StatusCode::Ok)
+        .assert_json(&json!({ "method": "POST", "query": {}, "body": body }));
+}
+
+#[test]
+fn sends_form() {
+    let mut form = BTreeMap::new();
+    form.insert("title", "Writium");
+    client().put("/echo").with_form(&form).send()
+        .assert_status(StatusCode::Ok)
+        .assert_json(&json!({ "method": "PUT", "query": {}, "body": { "title": "Writium" } }));
+}
+
+#[test]
+fn sends_headers() {
+    client().get("/echo").with_header(UserAgent::new("tester")).send()
+        .assert_raw_header("X-Echo", "tester");
+    client().get("/echo").with_raw_header("User-Agent", "raw").send()
+        .assert_raw_header("X-Echo", "raw");
+}
+
+#[test]
+fn strips_body_of_head() {
+    let body = json!({ "method": "HEAD", "query": {}, "body": null });
+    let len = body.to_string().len() as u64;
+    client().head("/echo").send()
+        .assert_status(StatusCode::Ok)
+        .assert_header(ContentLength(len))
+        .assert_body("");
+}
+
+#[test]
+fn reports_client_errors() {
+    let res = client().get("/echo").with_query("a[b]=1").send();
+    res.assert_status(StatusCode::UnprocessableEntity)
+        .assert_header(ContentType("application/problem+json".parse().unwrap()));
+    assert_eq!(res.json::<Value>()["field"], "a");
+}
+
+#[test]
+#[should_panic(expected = "expected status `201 Created`, got:\n200 OK\n")]
+fn assert_status_panics() {
+    client().get("/echo").send().assert_status(StatusCode::Created);
+}
+
+#[test]
+#[should_panic(expected = "expected header `Server: Server(\"a\")`, got none:\n200 OK\n")]
+fn assert_header_panics_if_absent() {
+    client().get("/echo").send().assert_header(Server::new("a"));
+}
+
+#[test]
+#[should_panic(expected = "expected header `Content-Length: ContentLength(1)`, got `ContentLength(")]
+fn assert_header_panics_if_different() {
+    client().get("/echo").send().assert_header(ContentLength(1));
+}
+
+#[test]
+#[should_panic(expected = "expected header `X-Echo: a`, got `b`:\n200 OK\n")]
+fn assert_raw_header_panics() {
+    client().get("/echo").with_header(UserAgent::new("b")).send()
+        .assert_raw_header("X-Echo", "a");
+}
+
+#[test]
+#[should_panic(expected = "expected no header `Content-Type`:\n200 OK\n")]
+fn assert_no_header_panics() {
+    client().get("/echo").send().assert_no_header::<ContentType>();
+}
+
+#[test]
+#[should_panic(expected = "expected body `nothing`, got:\n200 OK\n")]
+fn assert_body_panics() {
+    client().get("/echo").send().assert_body("nothing");
+}
+
+#[test]
+#[should_panic(expected = "expected JSON body `{\"method\":\"POST\"}`, got:\n200 OK\n")]
+fn assert_json_panics() {
+    client().get("/echo").send().assert_json(&json!({ "method": "POST" }));
+}
+
+#[test]
+#[should_panic(expected = "unable to deserialize JSON body")]
+fn json_panics_if_not_json() {
+    client().head("/echo").send().json::<Value>();
+}
@@ -0,0 +1,257 @@
+extern crate futures;
+extern crate tokio_core;
+extern crate writium;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use futures::{Future, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use writium::Writium;
+use writium::prelude::*;
+use writium::websocket::{Message, WebSocket};
+
+/// Key and accept value given as example in RFC 6455.
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+/// Echo data messages back.
+struct Echo {
+    max_message_size: usize,
+    started: Arc<AtomicBool>,
+}
+impl Echo {
+    fn new() -> Echo {
+        Echo {
+            max_message_size: 16 * 1024 * 1024,
+            started: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+impl WebSocketApi for Echo {
+    fn name(&self) -> &[&str] {
+        &["echo"]
+    }
+    fn accept(&self, _req: &mut Request) -> Result<Session> {
+        let max_message_size = self.max_message_size;
+        let started = self.started.clone();
+        let session = Session::new(move |mut ws: WebSocket| {
+            started.store(true, Ordering::SeqCst);
+            ws.set_max_message_size(max_message_size);
+            let (sink, stream) = ws.split();
+            stream
+                .filter(|msg| matches!(*msg, Message::Text(_) | Message::Binary(_)))
+                .forward(sink)
+                .then(|_| Ok(()))
+        });
+        Ok(session)
+    }
+}
+
+/// Turn accepted upgrades into errors.
+struct Forbid;
+impl Middleware for Forbid {
+    fn after(&self, _req: &Request, res: Response) -> ApiResult {
+        if res.status() == StatusCode::SwitchingProtocols {
+            Err(Error::forbidden("Upgrade is forbidden."))
+        } else {
+            Ok(res)
+        }
+    }
+}
+
+/// Serve connections on loopback on another thread.
+fn serve(writium: Writium) -> SocketAddr {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &handle).unwrap();
+        tx.send(listener.local_addr().unwrap()).unwrap();
+        let server = listener.incoming().for_each(|(sock, _)| {
+            handle.spawn(writium.serve_connection(sock).then(|_| Ok(())));
+            Ok(())
+        });
+        core.run(server).unwrap();
+    });
+    rx.recv().unwrap()
+}
+fn serve_api(api: Echo) -> SocketAddr {
+    let mut writium = Writium::new();
+    writium.bind_websocket(api);
+    serve(writium)
+}
+
+/// Send handshake request and read the response head.
+fn handshake(addr: SocketAddr) -> (TcpStream, String) {
+    let mut sock = TcpStream::connect(addr).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(sock, "GET /echo HTTP/1.1\r\nHost: localhost\r\n\
+        Upgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n", KEY).unwrap();
+    let head = read_head(&mut sock);
+    (sock, head)
+}
+fn connect(addr: SocketAddr) -> TcpStream {
+    let (sock, head) = handshake(addr);
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+    assert!(head.contains(&format!("Sec-WebSocket-Accept: {}\r\n", ACCEPT)), "{}", head);
+    sock
+}
+fn read_head(sock: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        sock.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn send_frame(sock: &mut TcpStream, fin: bool, opcode: u8, payload: &[u8], masked: bool) {
+    let mut buf = vec![if fin { 0x80 } else { 0 } | opcode];
+    let mask_bit = if masked { 0x80 } else { 0 };
+    let len = payload.len();
+    if len < 126 {
+        buf.push(mask_bit | len as u8);
+    } else if len <= 0xFFFF {
+        buf.push(mask_bit | 126);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(mask_bit | 127);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    if masked {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        buf.extend_from_slice(&mask);
+        buf.extend(payload.iter().enumerate().map(|(i, x)| x ^ mask[i % 4]));
+    } else {
+        buf.extend_from_slice(payload);
+    }
+    sock.write_all(&buf).unwrap();
+}
+fn send(sock: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    send_frame(sock, true, opcode, payload, true)
+}
+/// Receive a frame from server, which is never fragmented or masked.
+fn recv(sock: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    sock.read_exact(&mut head).unwrap();
+    assert_eq!(head[0] & 0xF0, 0x80, "unexpected flags");
+    assert_eq!(head[1] & 0x80, 0, "server frames must not be masked");
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            sock.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        },
+        127 => {
+            let mut len = [0; 8];
+            sock.read_exact(&mut len).unwrap();
+            u64::from_be_bytes(len) as usize
+        },
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    sock.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+fn recv_close_code(sock: &mut TcpStream) -> u16 {
+    let (opcode, payload) = recv(sock);
+    assert_eq!(opcode, 0x8);
+    u16::from_be_bytes([payload[0], payload[1]])
+}
+fn assert_closed(sock: &mut TcpStream) {
+    let mut buf = [0; 16];
+    assert_eq!(sock.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn echo_messages_of_all_lengths() {
+    let mut sock = connect(serve_api(Echo::new()));
+    send(&mut sock, 0x1, b"hello");
+    assert_eq!(recv(&mut sock), (0x1, b"hello".to_vec()));
+    // 16-bit and 64-bit extended payload lengths.
+    for &len in &[300, 70000] {
+        let data: Vec<u8> = (0..len).map(|x| x as u8).collect();
+        send(&mut sock, 0x2, &data);
+        assert_eq!(recv(&mut sock), (0x2, data));
+    }
+}
+
+#[test]
+fn fragments_with_interleaved_ping() {
+    let mut sock = connect(serve_api(Echo::new()));
+    send_frame(&mut sock, false, 0x1, b"Hel", true);
+    send(&mut sock, 0x9, b"ping");
+    send_frame(&mut sock, false, 0x0, b"lo", true);
+    send_frame(&mut sock, true, 0x0, b"!", true);
+    assert_eq!(recv(&mut sock), (0xA, b"ping".to_vec()));
+    assert_eq!(recv(&mut sock), (0x1, b"Hello!".to_vec()));
+}
+
+#[test]
+fn close_is_echoed() {
+    let mut sock = connect(serve_api(Echo::new()));
+    let mut payload = 1001u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"bye");
+    send(&mut sock, 0x8, &payload);
+    assert_eq!(recv_close_code(&mut sock), 1001);
+    assert_closed(&mut sock);
+}
+
+#[test]
+fn oversized_message_is_rejected() {
+    let mut api = Echo::new();
+    api.max_message_size = 1024;
+    let mut sock = connect(serve_api(api));
+    send_frame(&mut sock, false, 0x2, &[0; 1000], true);
+    send_frame(&mut sock, true, 0x0, &[0; 100], true);
+    assert_eq!(recv_close_code(&mut sock), 1009);
+}
+
+#[test]
+fn unmasked_frame_is_rejected() {
+    let mut sock = connect(serve_api(Echo::new()));
+    send_frame(&mut sock, true, 0x1, b"hello", false);
+    assert_eq!(recv_close_code(&mut sock), 1002);
+}
+
+#[test]
+fn replaced_response_does_not_upgrade() {
+    let api = Echo::new();
+    let started = api.started.clone();
+    let mut writium = Writium::new();
+    writium.attach(Forbid);
+    writium.bind_websocket(api);
+    let (sock, head) = handshake(serve(writium));
+    assert!(head.starts_with("HTTP/1.1 403 "), "{}", head);
+    drop(sock);
+    thread::sleep(Duration::from_millis(200));
+    assert!(!started.load(Ordering::SeqCst));
+}
+
+#[test]
+fn reserved_close_code_is_not_echoed() {
+    for &code in &[1005u16, 1006, 1015, 999, 2000] {
+        let mut sock = connect(serve_api(Echo::new()));
+        send(&mut sock, 0x8, &code.to_be_bytes());
+        assert_eq!(recv_close_code(&mut sock), 1002);
+    }
+}
+
+#[test]
+fn invalid_length_is_rejected() {
+    // The most significant bit of 64-bit lengths must be 0.
+    let mut sock = connect(serve_api(Echo::new()));
+    let mut buf = vec![0x82, 0xFF];
+    buf.extend_from_slice(&u64::MAX.to_be_bytes());
+    sock.write_all(&buf).unwrap();
+    assert_eq!(recv_close_code(&mut sock), 1002);
+}